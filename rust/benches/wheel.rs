@@ -0,0 +1,97 @@
+//! Compares scheduling one deadline per key on a [`TimerWheel`] against one task per key, each
+//! with its own `tokio::time::sleep`, the way running a `ThrottledReceiver` per key would.
+//!
+//! Run with `cargo bench --bench wheel`. Both sides run on tokio's paused clock, so the timings
+//! only measure the cost of scheduling and firing the timers rather than waiting for them.
+
+use std::time::Instant as WallClock;
+
+use rust::wheel::TimerWheel;
+use tokio::{
+    runtime::{self, Runtime},
+    task::JoinSet,
+    time::{Duration, Instant, sleep_until},
+};
+
+const KEYS: [usize; 3] = [1_000, 10_000, 100_000];
+const RUNS: u32 = 5;
+
+/// Deadlines spread pseudo-randomly over the next 10 seconds, the same for every run.
+fn deadlines(keys: usize) -> Vec<Duration> {
+    let mut state = 0x2545_f491_4f6c_dd1d_u64;
+    (0..keys)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            Duration::from_millis(state % 10_000)
+        })
+        .collect()
+}
+
+fn runtime() -> Runtime {
+    runtime::Builder::new_current_thread()
+        .enable_time()
+        .start_paused(true)
+        .build()
+        .unwrap()
+}
+
+/// Schedules every deadline on one wheel, and fires them from a single task.
+fn wheel(deadlines: &[Duration]) -> Duration {
+    runtime().block_on(async {
+        let started = WallClock::now();
+        let start = Instant::now();
+        let mut wheel = TimerWheel::new(start, Duration::from_millis(1));
+        for (key, &deadline) in deadlines.iter().enumerate() {
+            wheel.insert(start + deadline, key);
+        }
+
+        let mut fired = 0;
+        while let Some(deadline) = wheel.next_deadline() {
+            sleep_until(deadline).await;
+            while wheel.poll(Instant::now()).is_some() {
+                fired += 1;
+            }
+        }
+        assert_eq!(fired, deadlines.len());
+        started.elapsed()
+    })
+}
+
+/// Spawns a task per deadline that sleeps until it.
+fn sleep_per_key(deadlines: &[Duration]) -> Duration {
+    runtime().block_on(async {
+        let started = WallClock::now();
+        let start = Instant::now();
+        let mut tasks = JoinSet::new();
+        for &deadline in deadlines {
+            tasks.spawn(sleep_until(start + deadline));
+        }
+
+        let mut fired = 0;
+        while tasks.join_next().await.is_some() {
+            fired += 1;
+        }
+        assert_eq!(fired, deadlines.len());
+        started.elapsed()
+    })
+}
+
+/// The fastest of [`RUNS`] runs, to keep noise from the rest of the machine out.
+fn best_of(run: impl Fn() -> Duration) -> Duration {
+    (0..RUNS).map(|_| run()).min().unwrap()
+}
+
+fn main() {
+    println!(
+        "{:>8} {:>14} {:>14}",
+        "keys", "timer wheel", "sleep per key"
+    );
+    for keys in KEYS {
+        let deadlines = deadlines(keys);
+        let wheel = best_of(|| wheel(&deadlines));
+        let sleeps = best_of(|| sleep_per_key(&deadlines));
+        println!("{keys:>8} {wheel:>14.2?} {sleeps:>14.2?}");
+    }
+}
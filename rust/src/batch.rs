@@ -0,0 +1,213 @@
+use std::{future::Future, mem};
+
+use tokio::{sync::mpsc, time::Duration};
+
+use crate::{
+    Action, Summary, Throttle,
+    clock::{Clock, TokioClock},
+};
+
+/// What a [`BatchingReceiver`] does when a window closes without any values in it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EmptyBatches {
+    /// Emit nothing, and go idle until the next value arrives.
+    #[default]
+    Skip,
+    /// Emit an empty batch before going idle, so the handler can tell the values have stopped.
+    Emit,
+}
+
+/// Wraps an [`mpsc::Receiver`] so that its values are handed to a handler in batches at most once
+/// per `interval`, without dropping any of them.
+///
+/// Windows open and close like those of a [`ThrottledReceiver`](crate::ThrottledReceiver) with
+/// [`ThrottleEdge::Both`](crate::ThrottleEdge::Both): the value that opens a window is emitted
+/// straight away in a batch of its own, and everything else that arrives during the window is
+/// emitted together when it closes. Setting a `max_batch` emits a batch early once it's full,
+/// which starts a new window.
+#[derive(Debug)]
+pub struct BatchingReceiver<T, C = TokioClock> {
+    rx: mpsc::Receiver<T>,
+    max_batch: Option<usize>,
+    empty: EmptyBatches,
+    // Tracks the windows only, since the values live in the batch.
+    throttle: Throttle<()>,
+    clock: C,
+}
+
+impl<T> BatchingReceiver<T> {
+    /// Creates a batching receiver that emits at most once per `interval`.
+    pub fn new(rx: mpsc::Receiver<T>, interval: Duration) -> Self {
+        Self {
+            rx,
+            max_batch: None,
+            empty: EmptyBatches::default(),
+            throttle: Throttle::new(interval),
+            clock: TokioClock,
+        }
+    }
+}
+
+impl<T, C: Clock> BatchingReceiver<T, C> {
+    /// Sets the most values a batch can hold before it's emitted early.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_batch` is zero.
+    pub fn max_batch(mut self, max_batch: usize) -> Self {
+        assert!(max_batch > 0, "batches must be able to hold a value");
+        self.max_batch = Some(max_batch);
+        self
+    }
+
+    /// Sets what happens when a window closes empty. Defaults to [`EmptyBatches::Skip`].
+    pub fn empty_batches(mut self, empty: EmptyBatches) -> Self {
+        self.empty = empty;
+        self
+    }
+
+    /// Sets the clock that windows are timed by. Defaults to [`TokioClock`].
+    pub fn clock<C2: Clock>(self, clock: C2) -> BatchingReceiver<T, C2> {
+        BatchingReceiver {
+            rx: self.rx,
+            max_batch: self.max_batch,
+            empty: self.empty,
+            throttle: self.throttle,
+            clock,
+        }
+    }
+
+    /// Runs the batching loop, awaiting `handler` with each batch, until every [`mpsc::Sender`]
+    /// is dropped. The values still waiting for their window to close are flushed straight away
+    /// once the senders are dropped.
+    pub async fn run<F, Fut>(mut self, mut handler: F) -> Summary
+    where
+        F: FnMut(Vec<T>) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        let mut summary = Summary::default();
+        let mut batch = Vec::new();
+
+        loop {
+            let deadline = self.throttle.next_deadline();
+            tokio::select! {
+                _ = self.clock.sleep_until(deadline.unwrap_or_else(|| self.clock.now())),
+                    if deadline.is_some() =>
+                {
+                    // The deadline has passed, so nothing to emit means the window was empty.
+                    if self.throttle.on_deadline(self.clock.now()).is_none()
+                        && self.empty == EmptyBatches::Skip
+                    {
+                        continue;
+                    }
+                }
+                value = self.rx.recv() => {
+                    let Some(value) = value else {
+                        if self.throttle.on_close().is_some() {
+                            handler(batch).await;
+                            summary.emitted += 1;
+                            summary.flushed_on_close = true;
+                        }
+                        return summary;
+                    };
+                    batch.push(value);
+                    let full = self.max_batch.is_some_and(|max_batch| batch.len() >= max_batch);
+                    match self.throttle.on_value(self.clock.now(), ()) {
+                        Action::Emit(()) => {}
+                        Action::Hold if full => {
+                            self.throttle.flush(self.clock.now());
+                        }
+                        Action::Hold | Action::Discard => continue,
+                    }
+                }
+            }
+
+            handler(mem::take(&mut batch)).await;
+            summary.emitted += 1;
+            self.throttle.on_handled(self.clock.now());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{sync::mpsc, time::Duration};
+
+    use super::{BatchingReceiver, EmptyBatches};
+    use crate::{
+        Summary,
+        test_util::{PAIRS, ScriptedSender, TICK_MS, replay_mpsc},
+    };
+
+    /// Sends [`PAIRS`] into the receiver built by `receiver`, dropping the sender at 4500ms, and
+    /// returns each batch with when it was emitted.
+    async fn run_batches(
+        receiver: impl FnOnce(mpsc::Receiver<&'static str>) -> BatchingReceiver<&'static str>,
+    ) -> (Vec<(u128, Vec<&'static str>)>, Summary) {
+        let script = ScriptedSender::marbles(PAIRS, TICK_MS).close_at(4500);
+        replay_mpsc(script, |rx, emits| {
+            receiver(rx).run(move |batch| {
+                emits.push(batch);
+                async {}
+            })
+        })
+        .await
+    }
+
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn batches_every_value_in_each_window() {
+        let (emitted, summary) =
+            run_batches(|rx| BatchingReceiver::new(rx, Duration::from_millis(1000))).await;
+
+        // The same windows as the throttle, but "c" and "e" through "h" aren't dropped.
+        assert_eq!(
+            emitted,
+            [
+                (0, vec!["a"]),
+                (1000, vec!["b"]),
+                (2000, vec!["c", "d"]),
+                (3000, vec!["e", "f", "g", "h", "i"]),
+            ]
+        );
+        assert_eq!(
+            summary,
+            Summary {
+                emitted: 4,
+                flushed_on_close: false,
+                stats: None,
+            }
+        );
+    }
+
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn full_batch_is_emitted_early() {
+        let (emitted, _) =
+            run_batches(|rx| BatchingReceiver::new(rx, Duration::from_millis(1000)).max_batch(3))
+                .await;
+
+        // "g" fills the batch at 2100ms, which starts a new window that "h" and "i" wait out.
+        assert_eq!(
+            emitted,
+            [
+                (0, vec!["a"]),
+                (1000, vec!["b"]),
+                (2000, vec!["c", "d"]),
+                (2100, vec!["e", "f", "g"]),
+                (3100, vec!["h", "i"]),
+            ]
+        );
+    }
+
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn empty_window_can_emit_empty_batch() {
+        let (emitted, _) = run_batches(|rx| {
+            BatchingReceiver::new(rx, Duration::from_millis(1000)).empty_batches(EmptyBatches::Emit)
+        })
+        .await;
+
+        assert_eq!(
+            emitted[3..],
+            [(3000, vec!["e", "f", "g", "h", "i"]), (4000, vec![])]
+        );
+    }
+}
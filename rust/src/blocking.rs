@@ -0,0 +1,357 @@
+//! A latest-value channel and throttled receiver for synchronous code, like worker threads and
+//! FFI callbacks, that can't await a [`watch`](tokio::sync::watch) channel.
+//!
+//! The channel behaves like [`watch`](tokio::sync::watch): every send overwrites the value, and
+//! each receiver only sees a value once. It's built on a [`Mutex`] and [`Condvar`], so receiving
+//! blocks the calling thread and needs no runtime.
+
+use std::{
+    error::Error,
+    fmt,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{Action, Schedule, Summary, Throttle, ThrottleEdge, sync};
+
+/// Returned by [`Sender::send`] once every receiver has been dropped, with the value that
+/// couldn't be sent.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+impl<T> fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("channel closed")
+    }
+}
+
+impl<T: fmt::Debug> Error for SendError<T> {}
+
+/// Returned by [`Receiver::recv`] once the sender has been dropped and the latest value has been
+/// seen.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RecvError;
+
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("channel closed")
+    }
+}
+
+impl Error for RecvError {}
+
+#[derive(Debug)]
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    changed: Condvar,
+}
+
+#[derive(Debug)]
+struct State<T> {
+    value: T,
+    /// Bumped on every send, so receivers can tell which values they've seen.
+    version: u64,
+    sender_dropped: bool,
+    receivers: usize,
+}
+
+impl<T> Shared<T> {
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        sync::lock(&self.state)
+    }
+}
+
+/// Sends values to every [`Receiver`] of a [`channel`], overwriting the previous value.
+#[derive(Debug)]
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Overwrites the value and wakes every receiver, failing if every receiver has been dropped.
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        let mut state = self.shared.lock();
+        if state.receivers == 0 {
+            return Err(SendError(value));
+        }
+        state.value = value;
+        state.version += 1;
+        drop(state);
+        self.shared.changed.notify_all();
+        Ok(())
+    }
+
+    /// Creates another receiver, which has already seen the current value.
+    pub fn subscribe(&self) -> Receiver<T> {
+        let mut state = self.shared.lock();
+        state.receivers += 1;
+        Receiver {
+            shared: self.shared.clone(),
+            seen: state.version,
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.shared.lock().sender_dropped = true;
+        self.shared.changed.notify_all();
+    }
+}
+
+/// Receives the latest value of a [`channel`], blocking the calling thread until it changes.
+#[derive(Debug)]
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+    /// The version of the last value this receiver saw.
+    seen: u64,
+}
+
+impl<T: Clone> Receiver<T> {
+    /// Blocks until there's a value this receiver hasn't seen yet and returns it, failing once
+    /// the sender has been dropped and the latest value has been seen.
+    pub fn recv(&mut self) -> Result<T, RecvError> {
+        self.recv_until(None)
+            .map(|value| value.expect("recv without a deadline timed out"))
+    }
+
+    /// Like [`recv`](Receiver::recv), but gives up at `deadline`, returning `Ok(None)` if no
+    /// value arrived by then.
+    pub fn recv_deadline(&mut self, deadline: Instant) -> Result<Option<T>, RecvError> {
+        self.recv_until(Some(deadline))
+    }
+
+    fn recv_until(&mut self, deadline: Option<Instant>) -> Result<Option<T>, RecvError> {
+        let mut state = self.shared.lock();
+        loop {
+            if state.version != self.seen {
+                self.seen = state.version;
+                return Ok(Some(state.value.clone()));
+            }
+            if state.sender_dropped {
+                return Err(RecvError);
+            }
+            state = match deadline {
+                None => sync::wait(&self.shared.changed, state),
+                Some(deadline) => {
+                    let timeout = deadline.saturating_duration_since(Instant::now());
+                    if timeout.is_zero() {
+                        return Ok(None);
+                    }
+                    sync::wait_timeout(&self.shared.changed, state, timeout)
+                }
+            };
+        }
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        self.shared.lock().receivers += 1;
+        Self {
+            shared: self.shared.clone(),
+            seen: self.seen,
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.lock().receivers -= 1;
+    }
+}
+
+/// Creates a blocking latest-value channel, whose receiver has already seen `init`.
+pub fn channel<T>(init: T) -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            value: init,
+            version: 0,
+            sender_dropped: false,
+            receivers: 1,
+        }),
+        changed: Condvar::new(),
+    });
+    let rx = Receiver {
+        shared: shared.clone(),
+        seen: 0,
+    };
+    (Sender { shared }, rx)
+}
+
+/// The blocking counterpart of [`crate::ThrottledReceiver`], with the same semantics, that runs
+/// its handler on the calling thread.
+#[derive(Debug)]
+pub struct ThrottledReceiver<T> {
+    rx: Receiver<T>,
+    throttle: Throttle<T>,
+}
+
+impl<T: Clone> ThrottledReceiver<T> {
+    /// Creates a throttled receiver that emits at most once per `interval`.
+    pub fn new(rx: Receiver<T>, interval: Duration) -> Self {
+        Self {
+            rx,
+            throttle: Throttle::new(interval),
+        }
+    }
+
+    /// Sets which edges of each window emit a value. Defaults to [`ThrottleEdge::Both`].
+    pub fn edge(mut self, edge: ThrottleEdge) -> Self {
+        self.throttle = self.throttle.edge(edge);
+        self
+    }
+
+    /// Sets how the end of each window is scheduled. Defaults to [`Schedule::FixedDelay`].
+    pub fn schedule(mut self, schedule: Schedule) -> Self {
+        self.throttle = self.throttle.schedule(schedule);
+        self
+    }
+
+    /// The minimum time between two emits.
+    pub fn interval(&self) -> Duration {
+        self.throttle.interval()
+    }
+
+    /// Runs the throttle loop on the calling thread, calling `handler` with each emitted value,
+    /// until the [`Sender`] is dropped. A pending trailing value is flushed straight away when
+    /// the sender is dropped, as with [`crate::ThrottledReceiver::run`].
+    pub fn run(mut self, mut handler: impl FnMut(T)) -> Summary {
+        let mut summary = Summary::default();
+        self.throttle.set_wall_clock(now(), SystemTime::now());
+
+        loop {
+            let received = match self.throttle.next_deadline() {
+                Some(deadline) => self.rx.recv_deadline(deadline.into_std()),
+                None => self.rx.recv().map(Some),
+            };
+            let value = match received {
+                Ok(Some(value)) => match self.throttle.on_value(now(), value) {
+                    Action::Emit(value) => Some(value),
+                    Action::Hold | Action::Discard => None,
+                },
+                Ok(None) => self.throttle.on_deadline(now()),
+                Err(_) => {
+                    if let Some(value) = self.throttle.on_close() {
+                        handler(value);
+                        summary.emitted += 1;
+                        summary.flushed_on_close = true;
+                    }
+                    return summary;
+                }
+            };
+            if let Some(value) = value {
+                handler(value);
+                summary.emitted += 1;
+                self.throttle.on_handled(now());
+            }
+        }
+    }
+}
+
+/// The current time, in the form [`Throttle`] takes it.
+fn now() -> tokio::time::Instant {
+    tokio::time::Instant::from_std(Instant::now())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::mpsc,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use super::{RecvError, SendError, ThrottledReceiver, channel};
+    use crate::{
+        Summary,
+        test_util::{PAIRS, TICK_MS, marble},
+    };
+
+    #[test]
+    fn receivers_see_each_value_once() {
+        let (tx, mut rx) = channel("");
+        let mut other = tx.subscribe();
+
+        tx.send("a").unwrap();
+        tx.send("b").unwrap();
+        assert_eq!(rx.recv(), Ok("b"));
+        assert_eq!(
+            rx.recv_deadline(Instant::now() + Duration::from_millis(10)),
+            Ok(None)
+        );
+        assert_eq!(other.recv(), Ok("b"));
+
+        // The latest value is still delivered after the sender is dropped.
+        tx.send("c").unwrap();
+        drop(tx);
+        assert_eq!(rx.recv(), Ok("c"));
+        assert_eq!(rx.recv(), Err(RecvError));
+    }
+
+    #[test]
+    fn send_fails_without_receivers() {
+        let (tx, rx) = channel("");
+        drop(rx);
+        assert_eq!(tx.send("a"), Err(SendError("a")));
+    }
+
+    /// Replays [`PAIRS`] in real time from another thread, timing each send from the emit that
+    /// opened its window rather than from the start. A late emit pushes the sends after it back
+    /// with it, so every send still lands at least 50ms after the boundary it follows.
+    #[test]
+    fn throttle_outputs_expected_messages() {
+        let (tx, rx) = channel("");
+        let (emits_tx, emits) = mpsc::channel();
+        let pairs = marble::parse(PAIRS, TICK_MS).unwrap();
+        let start = Instant::now();
+
+        let sender = thread::spawn(move || {
+            // When each emit happened. The first window opens at the start, and each after it at
+            // the emit that ended the one before, which is the emit with the same index.
+            let mut emitted_at = Vec::new();
+            let mut opened = |window: usize| {
+                if window == 0 {
+                    return start;
+                }
+                while emitted_at.len() <= window {
+                    emitted_at.push(emits.recv().unwrap());
+                }
+                emitted_at[window]
+            };
+            for (time, msg) in pairs {
+                let window = (time / 1000) as usize;
+                let at = opened(window) + Duration::from_millis(time % 1000);
+                thread::sleep(at.saturating_duration_since(Instant::now()));
+                tx.send(msg).unwrap();
+            }
+            // Wait for "i" to be emitted at the end of its window before closing.
+            opened(3);
+        });
+
+        let mut emitted = Vec::new();
+        let summary = ThrottledReceiver::new(rx, Duration::from_millis(1000)).run(|value| {
+            emitted.push((value, start.elapsed()));
+            emits_tx.send(Instant::now()).unwrap();
+        });
+        sender.join().unwrap();
+
+        let values: Vec<_> = emitted.iter().map(|(value, _)| *value).collect();
+        assert_eq!(values, ["a", "b", "d", "i"]);
+        // Each emit waits at least the interval after the one before, however late it runs.
+        for pair in emitted.windows(2) {
+            assert!(
+                pair[1].1 - pair[0].1 >= Duration::from_millis(1000),
+                "{emitted:?}"
+            );
+        }
+        assert_eq!(
+            summary,
+            Summary {
+                emitted: 4,
+                flushed_on_close: false,
+                stats: None,
+            }
+        );
+    }
+}
@@ -0,0 +1,221 @@
+use std::sync::{Mutex, MutexGuard};
+
+use tokio::time::{Duration, Instant};
+
+use crate::{
+    RateLimiter, RetryAfter,
+    clock::{Clock, TokioClock},
+    limit, sync,
+};
+
+#[derive(Debug)]
+struct State {
+    /// How long the tokens in the bucket took to refill, which keeps partial tokens exact.
+    credit: Duration,
+    /// When `credit` was last brought up to date.
+    updated: Instant,
+}
+
+/// A token bucket that holds up to `capacity` tokens and refills at `refill_rate` tokens per
+/// second, for limiting outbound calls while still allowing bursts.
+///
+/// Unlike the fixed spacing of a [`ThrottledReceiver`](crate::ThrottledReceiver), a full bucket
+/// lets up to `capacity` calls through straight away, after which they're paced by the refill
+/// rate. Each call can take a different number of tokens, to weight expensive calls more heavily.
+///
+/// The bucket starts full, and can be shared between tasks behind an [`Arc`](std::sync::Arc).
+#[derive(Debug)]
+pub struct TokenBucket<C = TokioClock> {
+    capacity: u32,
+    /// How long a single token takes to refill.
+    per_token: Duration,
+    state: Mutex<State>,
+    clock: C,
+}
+
+impl TokenBucket {
+    /// Creates a full bucket of `capacity` tokens that refills at `refill_rate` tokens per
+    /// second.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is zero, or `refill_rate` isn't a positive number. It also panics if
+    /// `refill_rate` is over a billion, or so low that the bucket would take more than a century
+    /// to fill.
+    pub fn new(capacity: u32, refill_rate: f64) -> Self {
+        assert!(capacity > 0, "the bucket must be able to hold a token");
+        assert!(
+            refill_rate > 0.0 && refill_rate.is_finite(),
+            "the bucket must refill at a positive rate"
+        );
+        let per_token = limit::per_unit(refill_rate, capacity);
+        Self {
+            capacity,
+            per_token,
+            state: Mutex::new(State {
+                credit: per_token * capacity,
+                updated: Instant::now(),
+            }),
+            clock: TokioClock,
+        }
+    }
+}
+
+impl<C: Clock> TokenBucket<C> {
+    /// Sets the clock that the bucket refills by. Defaults to [`TokioClock`].
+    pub fn clock<C2: Clock>(self, clock: C2) -> TokenBucket<C2> {
+        let mut state = sync::into_inner(self.state);
+        state.updated = clock.now();
+        TokenBucket {
+            capacity: self.capacity,
+            per_token: self.per_token,
+            state: Mutex::new(state),
+            clock,
+        }
+    }
+
+    /// The most tokens the bucket can hold.
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    /// How many whole tokens are in the bucket right now.
+    pub fn available(&self) -> u32 {
+        let state = self.refilled(self.clock.now());
+        // Never more than `capacity`, since the credit is capped when it's refilled.
+        (state.credit.as_nanos() / self.per_token.as_nanos()) as u32
+    }
+
+    /// Takes `cost` tokens if the bucket has them, without waiting.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cost` is more than the bucket's capacity.
+    pub fn try_acquire(&self, cost: u32) -> bool {
+        self.check(self.clock.now(), cost).is_ok()
+    }
+
+    /// Takes `cost` tokens, waiting for the bucket to refill if it doesn't have them yet.
+    ///
+    /// The tokens are only taken once they're all there, so dropping the future gives nothing
+    /// up. Waiters aren't served in order, though: a cheap call can get in ahead of an expensive
+    /// one that's been waiting longer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cost` is more than the bucket's capacity, since it could never be met.
+    pub async fn acquire(&self, cost: u32) {
+        limit::acquire(self, &self.clock, cost).await;
+    }
+
+    /// Works out how long a call costing `cost` has to wait for the bucket in `state` to refill
+    /// enough for it.
+    fn wait(&self, state: &State, cost: u32) -> Result<(), RetryAfter> {
+        assert!(
+            cost <= self.capacity,
+            "a cost of {cost} can't be met by a bucket of {}",
+            self.capacity
+        );
+        match (self.per_token * cost).checked_sub(state.credit) {
+            Some(short) if !short.is_zero() => Err(RetryAfter(short)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Locks the state, adding whatever has refilled since it was last updated.
+    fn refilled(&self, now: Instant) -> MutexGuard<'_, State> {
+        let mut state = sync::lock(&self.state);
+        let elapsed = now.saturating_duration_since(state.updated);
+        state.credit = (state.credit + elapsed).min(self.per_token * self.capacity);
+        state.updated = state.updated.max(now);
+        state
+    }
+}
+
+impl<C: Clock> RateLimiter for TokenBucket<C> {
+    fn check(&self, now: Instant, cost: u32) -> Result<(), RetryAfter> {
+        let mut state = self.refilled(now);
+        self.wait(&state, cost)?;
+        state.credit -= self.per_token * cost;
+        Ok(())
+    }
+
+    fn peek(&self, now: Instant, cost: u32) -> Result<(), RetryAfter> {
+        self.wait(&self.refilled(now), cost)
+    }
+
+    fn is_idle(&self, now: Instant) -> bool {
+        self.refilled(now).credit == self.per_token * self.capacity
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::{task::JoinSet, time::Instant};
+
+    use super::TokenBucket;
+    use crate::{clock::MockClock, test_util::ms};
+
+    #[test]
+    fn bursts_up_to_capacity_then_refills() {
+        let clock = MockClock::new();
+        let bucket = TokenBucket::new(5, 10.0).clock(clock.clone());
+
+        assert!(bucket.try_acquire(2));
+        assert!(bucket.try_acquire(3));
+        assert!(!bucket.try_acquire(1));
+
+        // A token refills every 100ms, and half of one isn't enough.
+        clock.advance(ms(150));
+        assert_eq!(bucket.available(), 1);
+        assert!(!bucket.try_acquire(2));
+        clock.advance(ms(50));
+        assert!(bucket.try_acquire(2));
+
+        // The bucket stops filling once it's full.
+        clock.advance(ms(10_000));
+        assert_eq!(bucket.available(), 5);
+    }
+
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn acquire_waits_for_weighted_costs() {
+        let bucket = TokenBucket::new(4, 10.0);
+        let start = Instant::now();
+        let mut acquired = Vec::new();
+
+        for cost in [4, 1, 3, 2] {
+            bucket.acquire(cost).await;
+            acquired.push(start.elapsed().as_millis());
+        }
+
+        // The first burst empties the bucket, and each call after waits out its own cost.
+        assert_eq!(acquired, [0, 100, 400, 600]);
+    }
+
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn shared_bucket_paces_concurrent_calls() {
+        let bucket = Arc::new(TokenBucket::new(2, 4.0));
+        let start = Instant::now();
+        let mut calls = JoinSet::new();
+
+        for _ in 0..6 {
+            let bucket = bucket.clone();
+            calls.spawn(async move {
+                bucket.acquire(1).await;
+                start.elapsed().as_millis()
+            });
+        }
+
+        let mut times = calls.join_all().await;
+        times.sort();
+        assert_eq!(times, [0, 0, 250, 500, 750, 1000]);
+    }
+
+    #[tokio::test]
+    #[should_panic = "can't be met"]
+    async fn cost_over_capacity_panics() {
+        TokenBucket::new(2, 1.0).acquire(3).await;
+    }
+}
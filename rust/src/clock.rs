@@ -0,0 +1,402 @@
+//! Sources of time for the timing recipes, so they can run on something other than tokio's timer.
+//!
+//! Recipes default to [`TokioClock`], which follows tokio's paused clock in tests like any other
+//! tokio timer. [`MockClock`] only moves when it's told to, for simulating time in tests without
+//! tokio's `test-util` feature, and [`StdClock`] reads the system's monotonic clock directly.
+//!
+//! The [`stream`](crate::stream) adapters always use tokio's timer, since they hold their sleep
+//! pinned inside the stream.
+
+use std::{
+    collections::{BTreeMap, btree_map::Entry},
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Condvar, Mutex, Once},
+    task::{Context, Poll, Waker},
+    thread,
+    time::SystemTime,
+};
+
+use tokio::time::{Duration, Instant};
+
+use crate::sync;
+
+/// A source of the current time and of timers that fire at a given time.
+pub trait Clock: Clone + Send + Sync + 'static {
+    /// The current time on this clock.
+    fn now(&self) -> Instant;
+
+    /// The current wall-clock time on this clock, which
+    /// [`Schedule::FixedRate`](crate::Schedule::FixedRate) lines its grid up with when it's
+    /// aligned to the wall clock.
+    fn wall_time(&self) -> SystemTime;
+
+    /// Waits until `deadline` on this clock, finishing straight away if it has already passed.
+    fn sleep_until(&self, deadline: Instant) -> impl Future<Output = ()> + Send;
+}
+
+/// The clock of the current tokio runtime, which follows `tokio::time::pause`. Its wall-clock
+/// time is the system's.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TokioClock;
+
+impl Clock for TokioClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn wall_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> impl Future<Output = ()> + Send {
+        tokio::time::sleep_until(deadline)
+    }
+}
+
+/// The system's monotonic clock, read through [`std::time::Instant`] regardless of any runtime.
+///
+/// Sleeps are timed by a single thread shared by every `StdClock`, which is started the first
+/// time a sleep has to wait, so any executor can drive them.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StdClock;
+
+impl Clock for StdClock {
+    fn now(&self) -> Instant {
+        Instant::from_std(std::time::Instant::now())
+    }
+
+    fn wall_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> impl Future<Output = ()> + Send {
+        StdSleep { deadline, id: None }
+    }
+}
+
+/// The sleeps of every [`StdClock`], woken by the timer thread.
+struct StdTimers {
+    sleepers: Mutex<Sleepers>,
+    /// Notified when a sleep with an earlier deadline than any before it starts waiting.
+    changed: Condvar,
+}
+
+static STD_TIMERS: StdTimers = StdTimers {
+    sleepers: Mutex::new(Sleepers::new()),
+    changed: Condvar::new(),
+};
+
+/// Wakes each sleep of a [`StdClock`] once its deadline passes, for as long as the process runs.
+fn run_std_timers() {
+    let mut sleepers = sync::lock(&STD_TIMERS.sleepers);
+    loop {
+        let now = StdClock.now();
+        let woken = sleepers.take_due(now);
+        if !woken.is_empty() {
+            // Woken outside the lock, in case a waker polls the sleep straight away.
+            drop(sleepers);
+            woken.into_iter().for_each(Waker::wake);
+            sleepers = sync::lock(&STD_TIMERS.sleepers);
+            continue;
+        }
+        sleepers = match sleepers.next_deadline() {
+            Some(deadline) => sync::wait_timeout(&STD_TIMERS.changed, sleepers, deadline - now),
+            None => sync::wait(&STD_TIMERS.changed, sleepers),
+        };
+    }
+}
+
+/// A sleep on a [`StdClock`], which stops being timed once it's dropped.
+struct StdSleep {
+    deadline: Instant,
+    /// Set once the sleep is waiting on the timer thread.
+    id: Option<u64>,
+}
+
+impl Future for StdSleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if StdClock.now() >= self.deadline {
+            return Poll::Ready(());
+        }
+        static STARTED: Once = Once::new();
+        STARTED.call_once(|| {
+            thread::Builder::new()
+                .name("std-clock-timers".to_string())
+                .spawn(run_std_timers)
+                .expect("failed to spawn the StdClock timer thread");
+        });
+
+        let deadline = self.deadline;
+        let mut sleepers = sync::lock(&STD_TIMERS.sleepers);
+        if sleepers.register(deadline, &mut self.id, cx.waker()) {
+            STD_TIMERS.changed.notify_one();
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for StdSleep {
+    fn drop(&mut self) {
+        if let Some(id) = self.id {
+            sync::lock(&STD_TIMERS.sleepers).remove(self.deadline, id);
+        }
+    }
+}
+
+/// The wakers of sleeps that were polled before their deadline, keyed by deadline and an id, so
+/// that each sleep can replace or remove its own.
+#[derive(Debug)]
+struct Sleepers {
+    next_id: u64,
+    wakers: BTreeMap<(Instant, u64), Waker>,
+}
+
+impl Sleepers {
+    const fn new() -> Self {
+        Self {
+            next_id: 0,
+            wakers: BTreeMap::new(),
+        }
+    }
+
+    /// Registers `waker` for the sleep until `deadline`, giving the sleep an `id` if it doesn't
+    /// have one yet. Returns whether it's now the earliest sleep.
+    fn register(&mut self, deadline: Instant, id: &mut Option<u64>, waker: &Waker) -> bool {
+        let id = *id.get_or_insert_with(|| {
+            self.next_id += 1;
+            self.next_id
+        });
+        match self.wakers.entry((deadline, id)) {
+            // Polling the same sleep again shouldn't add another waker.
+            Entry::Occupied(mut entry) => {
+                entry.get_mut().clone_from(waker);
+                false
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(waker.clone());
+                self.next_deadline() == Some(deadline)
+            }
+        }
+    }
+
+    fn remove(&mut self, deadline: Instant, id: u64) {
+        self.wakers.remove(&(deadline, id));
+    }
+
+    fn next_deadline(&self) -> Option<Instant> {
+        self.wakers
+            .first_key_value()
+            .map(|(&(deadline, _), _)| deadline)
+    }
+
+    /// Removes every sleep whose deadline has been reached by `now`, returning their wakers.
+    fn take_due(&mut self, now: Instant) -> Vec<Waker> {
+        let mut woken = Vec::new();
+        while let Some(entry) = self.wakers.first_entry() {
+            if entry.key().0 > now {
+                break;
+            }
+            woken.push(entry.remove());
+        }
+        woken
+    }
+}
+
+/// A clock that stands still until [`advance`](MockClock::advance) is called.
+///
+/// Clones share the same time, so a test can keep one and hand the others to recipes. The wall
+/// clock starts at the system's time and moves along with it, unless it's set with
+/// [`set_wall_time`](MockClock::set_wall_time). Advancing the clock wakes every sleep whose
+/// deadline has been reached, but it's up to the test to let the woken tasks run, e.g. with
+/// [`tokio::task::yield_now`], before advancing again.
+#[derive(Clone, Debug)]
+pub struct MockClock {
+    state: Arc<Mutex<MockState>>,
+}
+
+#[derive(Debug)]
+struct MockState {
+    now: Instant,
+    wall_time: SystemTime,
+    sleepers: Sleepers,
+}
+
+impl MockClock {
+    /// Creates a clock that starts at the current time.
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(MockState {
+                now: Instant::now(),
+                wall_time: SystemTime::now(),
+                sleepers: Sleepers::new(),
+            })),
+        }
+    }
+
+    /// Moves the clock forward by `by`, waking every sleep whose deadline has now been reached.
+    pub fn advance(&self, by: Duration) {
+        let woken = {
+            let mut state = sync::lock(&self.state);
+            state.now += by;
+            state.wall_time += by;
+            let now = state.now;
+            state.sleepers.take_due(now)
+        };
+        // Woken outside the lock, in case a waker polls the sleep straight away.
+        woken.into_iter().for_each(Waker::wake);
+    }
+
+    /// Sets the wall-clock time without moving the monotonic time, like the system clock being
+    /// corrected. It moves on from there as the clock advances.
+    pub fn set_wall_time(&self, wall_time: SystemTime) {
+        sync::lock(&self.state).wall_time = wall_time;
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        sync::lock(&self.state).now
+    }
+
+    fn wall_time(&self) -> SystemTime {
+        sync::lock(&self.state).wall_time
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> impl Future<Output = ()> + Send {
+        MockSleep {
+            state: self.state.clone(),
+            deadline,
+            id: None,
+        }
+    }
+}
+
+/// A sleep on a [`MockClock`], which stops being woken once it's dropped.
+struct MockSleep {
+    state: Arc<Mutex<MockState>>,
+    deadline: Instant,
+    /// Set once the sleep is waiting for the clock to advance.
+    id: Option<u64>,
+}
+
+impl Future for MockSleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = &mut *self;
+        let mut state = sync::lock(&this.state);
+        if state.now >= this.deadline {
+            return Poll::Ready(());
+        }
+        state
+            .sleepers
+            .register(this.deadline, &mut this.id, cx.waker());
+        Poll::Pending
+    }
+}
+
+impl Drop for MockSleep {
+    fn drop(&mut self) {
+        if let Some(id) = self.id {
+            sync::lock(&self.state).sleepers.remove(self.deadline, id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        future::Future,
+        pin::pin,
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+        task::{Context, Wake, Waker},
+    };
+
+    use tokio::time::{Duration, timeout};
+
+    use super::{Clock, MockClock, STD_TIMERS, StdClock, StdSleep};
+    use crate::sync;
+
+    /// Counts how many times the task polling the sleep has been woken.
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.wake_by_ref();
+        }
+
+        fn wake_by_ref(self: &Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn mock_clock_only_moves_when_advanced() {
+        let clock = MockClock::new();
+        let start = clock.now();
+        let counter = Arc::new(CountingWaker(Default::default()));
+        let waker = counter.clone().into();
+        let mut cx = Context::from_waker(&waker);
+
+        let mut sleep = pin!(clock.sleep_until(start + Duration::from_millis(100)));
+        assert!(sleep.as_mut().poll(&mut cx).is_pending());
+        assert!(sleep.as_mut().poll(&mut cx).is_pending());
+
+        clock.advance(Duration::from_millis(99));
+        assert_eq!(counter.0.load(Ordering::SeqCst), 0);
+        assert!(sleep.as_mut().poll(&mut cx).is_pending());
+
+        // Polling twice registered the waker once.
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+        assert!(sleep.as_mut().poll(&mut cx).is_ready());
+        assert_eq!(clock.now(), start + Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn std_clock_sleeps_without_tokio_timer() {
+        let clock = StdClock;
+        let start = clock.now();
+        let sleep = |ms| clock.sleep_until(start + Duration::from_millis(ms));
+
+        // The sleeps share one timer thread, which wakes each at its own deadline.
+        let (_, (), ()) = tokio::join!(
+            timeout(Duration::from_millis(10), sleep(60_000)),
+            async { timeout(Duration::from_secs(5), sleep(20)).await.unwrap() },
+            async { timeout(Duration::from_secs(5), sleep(40)).await.unwrap() },
+        );
+        assert!(clock.now() >= start + Duration::from_millis(40));
+    }
+
+    #[test]
+    fn dropped_sleeps_stop_being_timed() {
+        let mut cx = Context::from_waker(Waker::noop());
+
+        let clock = MockClock::new();
+        let mut sleep = Box::pin(clock.sleep_until(clock.now() + Duration::from_millis(100)));
+        assert!(sleep.as_mut().poll(&mut cx).is_pending());
+        assert_eq!(sync::lock(&clock.state).sleepers.wakers.len(), 1);
+        drop(sleep);
+        assert!(sync::lock(&clock.state).sleepers.wakers.is_empty());
+
+        let deadline = StdClock.now() + Duration::from_secs(3600);
+        let mut sleep = Box::pin(StdSleep { deadline, id: None });
+        assert!(sleep.as_mut().poll(&mut cx).is_pending());
+        let key = (deadline, sleep.id.unwrap());
+        assert!(sync::lock(&STD_TIMERS.sleepers).wakers.contains_key(&key));
+        drop(sleep);
+        assert!(!sync::lock(&STD_TIMERS.sleepers).wakers.contains_key(&key));
+    }
+}
@@ -0,0 +1,207 @@
+use std::{future::Future, mem};
+
+use tokio::{sync::mpsc, time::Duration};
+
+use crate::{
+    Action, Schedule, Summary, Throttle, ThrottleEdge,
+    clock::{Clock, TokioClock},
+};
+
+/// Throttles an [`mpsc::Receiver`] like [`ThrottledReceiver`](crate::ThrottledReceiver), except
+/// that the values arriving during a window are folded into an accumulator with `fold` instead of
+/// overwriting each other.
+///
+/// Each window starts from `Acc::default()`, and the accumulator is emitted whenever the throttle
+/// would have emitted a value, so a counter can be summed, a maximum kept, or deltas upserted by
+/// key without losing any of them. With [`ThrottleEdge::Leading`], values that arrive during a
+/// window are still dropped.
+#[derive(Debug)]
+pub struct CoalescingThrottle<T, Acc, C = TokioClock> {
+    rx: mpsc::Receiver<T>,
+    fold: fn(&mut Acc, T),
+    // Tracks the windows only, since the values live in the accumulator.
+    throttle: Throttle<()>,
+    clock: C,
+}
+
+impl<T, Acc: Default> CoalescingThrottle<T, Acc> {
+    /// Creates a coalescing throttle that emits at most once per `interval`, folding values into
+    /// the accumulator with `fold`.
+    pub fn new(rx: mpsc::Receiver<T>, interval: Duration, fold: fn(&mut Acc, T)) -> Self {
+        Self {
+            rx,
+            fold,
+            throttle: Throttle::new(interval),
+            clock: TokioClock,
+        }
+    }
+}
+
+impl<T, Acc: Default, C: Clock> CoalescingThrottle<T, Acc, C> {
+    /// Sets which edges of each window emit the accumulator. Defaults to [`ThrottleEdge::Both`].
+    pub fn edge(mut self, edge: ThrottleEdge) -> Self {
+        self.throttle = self.throttle.edge(edge);
+        self
+    }
+
+    /// Sets how the end of each window is scheduled. Defaults to [`Schedule::FixedDelay`].
+    pub fn schedule(mut self, schedule: Schedule) -> Self {
+        self.throttle = self.throttle.schedule(schedule);
+        self
+    }
+
+    /// Sets the clock that windows are timed by. Defaults to [`TokioClock`].
+    pub fn clock<C2: Clock>(self, clock: C2) -> CoalescingThrottle<T, Acc, C2> {
+        CoalescingThrottle {
+            rx: self.rx,
+            fold: self.fold,
+            throttle: self.throttle,
+            clock,
+        }
+    }
+
+    /// Runs the throttle loop, awaiting `handler` with each emitted accumulator, until every
+    /// [`mpsc::Sender`] is dropped.
+    ///
+    /// Anything folded into the accumulator since the last emit is flushed straight away once the
+    /// senders are dropped, as long as the throttle emits trailing edges.
+    pub async fn run<F, Fut>(mut self, mut handler: F) -> Summary
+    where
+        F: FnMut(Acc) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        let mut summary = Summary::default();
+        let mut acc = Acc::default();
+        self.throttle
+            .set_wall_clock(self.clock.now(), self.clock.wall_time());
+
+        loop {
+            let deadline = self.throttle.next_deadline();
+            tokio::select! {
+                _ = self.clock.sleep_until(deadline.unwrap_or_else(|| self.clock.now())),
+                    if deadline.is_some() =>
+                {
+                    if self.throttle.on_deadline(self.clock.now()).is_some() {
+                        handler(mem::take(&mut acc)).await;
+                        summary.emitted += 1;
+                        self.throttle.on_handled(self.clock.now());
+                    }
+                }
+                value = self.rx.recv() => {
+                    let Some(value) = value else {
+                        if self.throttle.on_close().is_some() {
+                            handler(acc).await;
+                            summary.emitted += 1;
+                            summary.flushed_on_close = true;
+                        }
+                        return summary;
+                    };
+                    match self.throttle.on_value(self.clock.now(), ()) {
+                        Action::Emit(()) => {
+                            (self.fold)(&mut acc, value);
+                            handler(mem::take(&mut acc)).await;
+                            summary.emitted += 1;
+                            self.throttle.on_handled(self.clock.now());
+                        }
+                        Action::Hold => (self.fold)(&mut acc, value),
+                        Action::Discard => {}
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use tokio::{sync::mpsc, time::Duration};
+
+    use super::CoalescingThrottle;
+    use crate::{
+        Summary, ThrottleEdge,
+        test_util::{PAIRS, ScriptedSender, TICK_MS, replay_mpsc},
+    };
+
+    /// Sends each value of `marbles` into a coalescing throttle with a 1000ms interval, dropping
+    /// the sender after the last one, and returns each emitted accumulator with when it was
+    /// emitted.
+    async fn run_coalescing<Acc: Default>(
+        throttle: impl FnOnce(mpsc::Receiver<&'static str>) -> CoalescingThrottle<&'static str, Acc>,
+        marbles: &'static str,
+        tick_ms: u64,
+    ) -> (Vec<(u128, Acc)>, Summary) {
+        replay_mpsc(ScriptedSender::marbles(marbles, tick_ms), |rx, emits| {
+            throttle(rx).run(move |acc| {
+                emits.push(acc);
+                async {}
+            })
+        })
+        .await
+    }
+
+    fn append(acc: &mut String, value: &str) {
+        acc.push_str(value);
+    }
+
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn folds_values_within_each_window() {
+        let (emitted, summary) = run_coalescing(
+            |rx| CoalescingThrottle::new(rx, Duration::from_millis(1000), append),
+            PAIRS,
+            TICK_MS,
+        )
+        .await;
+
+        // Unlike the latest-wins throttle, "c" is folded in with "d" rather than lost, and the
+        // burst that's still pending when the sender is dropped is flushed straight away.
+        assert_eq!(
+            emitted,
+            [
+                (0, "a".to_string()),
+                (1000, "b".to_string()),
+                (2000, "cd".to_string()),
+                (2200, "efghi".to_string()),
+            ]
+        );
+        assert_eq!(
+            summary,
+            Summary {
+                emitted: 4,
+                flushed_on_close: true,
+                stats: None,
+            }
+        );
+    }
+
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn folds_keyed_upserts() {
+        // Each value is a key followed by its new level, and only the latest level per key is
+        // kept.
+        fn upsert(book: &mut BTreeMap<char, char>, delta: &str) {
+            let mut chars = delta.chars();
+            book.insert(chars.next().unwrap(), chars.next().unwrap());
+        }
+
+        let (tx, rx) = mpsc::channel(16);
+        for delta in ["x1", "y5", "x2", "y4", "z9"] {
+            tx.send(delta).await.unwrap();
+        }
+        drop(tx);
+        let mut books = Vec::new();
+        CoalescingThrottle::new(rx, Duration::from_millis(1000), upsert)
+            .edge(ThrottleEdge::Trailing)
+            .run(|book| {
+                books.push(book);
+                async {}
+            })
+            .await;
+        // Every delta lands in the window opened by the first one, which is flushed as soon as
+        // the sender is dropped.
+        assert_eq!(
+            books,
+            [BTreeMap::from([('x', '2'), ('y', '4'), ('z', '9')])]
+        );
+    }
+}
@@ -0,0 +1,434 @@
+use std::{collections::HashMap, error::Error, fmt, hash::Hash, sync::Mutex};
+
+use tokio::time::{Duration, Instant};
+
+use crate::{
+    RateLimiter, RetryAfter,
+    clock::{Clock, TokioClock},
+    sync,
+};
+
+/// Returned by [`CompositeLimiter::check`] when a call has to wait, with the limit that holds it
+/// up the longest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Blocked {
+    /// The name the binding limit was added with.
+    pub limit: &'static str,
+    /// How long until the binding limit would allow the call.
+    pub retry_after: Duration,
+}
+
+impl fmt::Display for Blocked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "rate limited by the {} limit, retry after {:?}",
+            self.limit, self.retry_after
+        )
+    }
+}
+
+impl Error for Blocked {}
+
+/// One of the limits of a [`CompositeLimiter`], which picks the limiter a call is checked
+/// against from its key.
+trait Layer<K>: Send + Sync {
+    fn check(&self, key: &K, now: Instant, cost: u32) -> Result<(), RetryAfter>;
+
+    fn peek(&self, key: &K, now: Instant, cost: u32) -> Result<(), RetryAfter>;
+
+    /// Drops whatever limiters are idle at `now`, if the layer creates them as it goes.
+    fn prune(&self, now: Instant);
+}
+
+/// A limit shared by every call.
+struct Shared<L>(L);
+
+impl<K, L: RateLimiter + Send + Sync> Layer<K> for Shared<L> {
+    fn check(&self, _: &K, now: Instant, cost: u32) -> Result<(), RetryAfter> {
+        self.0.check(now, cost)
+    }
+
+    fn peek(&self, _: &K, now: Instant, cost: u32) -> Result<(), RetryAfter> {
+        self.0.peek(now, cost)
+    }
+
+    fn prune(&self, _: Instant) {}
+}
+
+/// A limit with a separate limiter for each part of the key that `key` picks out.
+struct PerKey<K, Q, L> {
+    key: fn(&K) -> Q,
+    new: Box<dyn Fn() -> L + Send + Sync>,
+    limiters: Mutex<HashMap<Q, L>>,
+}
+
+impl<K, Q: Hash + Eq, L> PerKey<K, Q, L> {
+    /// Runs `f` with the limiter for `key`, creating it if this is the first call for it.
+    fn with<T>(&self, key: &K, f: impl FnOnce(&L) -> T) -> T {
+        let mut limiters = sync::lock(&self.limiters);
+        f(limiters.entry((self.key)(key)).or_insert_with(&self.new))
+    }
+}
+
+impl<K, Q, L> Layer<K> for PerKey<K, Q, L>
+where
+    Q: Hash + Eq + Send,
+    L: RateLimiter + Send,
+{
+    fn check(&self, key: &K, now: Instant, cost: u32) -> Result<(), RetryAfter> {
+        self.with(key, |limiter| limiter.check(now, cost))
+    }
+
+    fn peek(&self, key: &K, now: Instant, cost: u32) -> Result<(), RetryAfter> {
+        self.with(key, |limiter| limiter.peek(now, cost))
+    }
+
+    fn prune(&self, now: Instant) {
+        sync::lock(&self.limiters).retain(|_, limiter| !limiter.is_idle(now));
+    }
+}
+
+/// Applies several rate limits to each call at once, such as a global account limit, a limit
+/// per endpoint and a limit per symbol, only counting a call against any of them once every one
+/// allows it.
+///
+/// Each call is made with a key of type `K`, which per-key limits pick their part of out of. The
+/// composite owns its limiters and checks them one call at a time, so nothing else can use up a
+/// limit between finding that every limit allows a call and counting it against them. A blocked
+/// call is reported with whichever limit holds it up the longest, or the first one added if
+/// several hold it up as long.
+///
+/// Per-key limiters are created on a key's first call and kept until
+/// [`prune`](CompositeLimiter::prune) finds them idle, so they should be cheap, like a
+/// [`Gcra`](crate::Gcra), when there are many keys.
+pub struct CompositeLimiter<K = (), C = TokioClock> {
+    limits: Vec<(&'static str, Box<dyn Layer<K>>)>,
+    /// Held while a call is checked against every limit, so the check is atomic.
+    checking: Mutex<()>,
+    clock: C,
+}
+
+impl<K> CompositeLimiter<K> {
+    /// Creates a composite with no limits, which allows every call until some are added.
+    pub fn new() -> Self {
+        Self {
+            limits: Vec::new(),
+            checking: Mutex::new(()),
+            clock: TokioClock,
+        }
+    }
+}
+
+impl<K> Default for CompositeLimiter<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, C: Clock> CompositeLimiter<K, C> {
+    /// Adds a limit that every call is checked against, reported as `name` when it's binding.
+    pub fn limit(
+        mut self,
+        name: &'static str,
+        limiter: impl RateLimiter + Send + Sync + 'static,
+    ) -> Self {
+        self.limits.push((name, Box::new(Shared(limiter))));
+        self
+    }
+
+    /// Adds a limit with a separate limiter, created by `new`, for each value that `key` picks
+    /// out of the calls' keys. It's reported as `name` when it's binding.
+    pub fn per_key<Q, L>(
+        mut self,
+        name: &'static str,
+        key: fn(&K) -> Q,
+        new: impl Fn() -> L + Send + Sync + 'static,
+    ) -> Self
+    where
+        K: 'static,
+        Q: Hash + Eq + Send + 'static,
+        L: RateLimiter + Send + 'static,
+    {
+        let limit = PerKey {
+            key,
+            new: Box::new(new),
+            limiters: Mutex::new(HashMap::new()),
+        };
+        self.limits.push((name, Box::new(limit)));
+        self
+    }
+
+    /// Sets the clock that [`acquire`](CompositeLimiter::acquire) and
+    /// [`try_acquire`](CompositeLimiter::try_acquire) check the limits at. Defaults to
+    /// [`TokioClock`].
+    pub fn clock<C2: Clock>(self, clock: C2) -> CompositeLimiter<K, C2> {
+        CompositeLimiter {
+            limits: self.limits,
+            checking: self.checking,
+            clock,
+        }
+    }
+
+    /// Allows a call for `key` costing `cost` at `now` and counts it against every limit, or
+    /// returns the limit that's binding without counting it against any of them.
+    ///
+    /// That relies on each limiter's [`peek`](RateLimiter::peek) agreeing with its
+    /// [`check`](RateLimiter::check). Should a limit refuse a call its `peek` allowed, it's
+    /// returned as the binding one, though the limits before it have already counted the call.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cost` is more than any of the limits could ever allow at once.
+    pub fn check(&self, key: &K, now: Instant, cost: u32) -> Result<(), Blocked> {
+        let _checking = sync::lock(&self.checking);
+        let mut binding: Option<Blocked> = None;
+        for (name, limit) in &self.limits {
+            if let Err(RetryAfter(wait)) = limit.peek(key, now, cost)
+                && binding.is_none_or(|binding| wait > binding.retry_after)
+            {
+                binding = Some(Blocked {
+                    limit: name,
+                    retry_after: wait,
+                });
+            }
+        }
+        if let Some(binding) = binding {
+            return Err(binding);
+        }
+        for (name, limit) in &self.limits {
+            limit
+                .check(key, now, cost)
+                .map_err(|RetryAfter(wait)| Blocked {
+                    limit: name,
+                    retry_after: wait,
+                })?;
+        }
+        Ok(())
+    }
+
+    /// Forgets every per-key limiter whose calls have all been paid off by `now`, which a new one
+    /// would replace without changing what's allowed.
+    ///
+    /// Per-key limiters are never dropped otherwise, so this wants calling now and then when the
+    /// keys keep changing.
+    pub fn prune(&self, now: Instant) {
+        let _checking = sync::lock(&self.checking);
+        for (_, limit) in &self.limits {
+            limit.prune(now);
+        }
+    }
+
+    /// Allows a call for `key` costing `cost` if every limit allows it straight away, without
+    /// waiting.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cost` is more than any of the limits could ever allow at once.
+    pub fn try_acquire(&self, key: &K, cost: u32) -> Result<(), Blocked> {
+        self.check(key, self.clock.now(), cost)
+    }
+
+    /// Waits until every limit allows a call for `key` costing `cost`. Nothing is counted until
+    /// they all do, so dropping the future gives nothing up.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cost` is more than any of the limits could ever allow at once.
+    pub async fn acquire(&self, key: &K, cost: u32) {
+        loop {
+            let now = self.clock.now();
+            match self.check(key, now, cost) {
+                Ok(()) => return,
+                Err(blocked) => self.clock.sleep_until(now + blocked.retry_after).await,
+            }
+        }
+    }
+}
+
+impl<K, C: fmt::Debug> fmt::Debug for CompositeLimiter<K, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<_> = self.limits.iter().map(|(name, _)| name).collect();
+        f.debug_struct("CompositeLimiter")
+            .field("limits", &names)
+            .field("clock", &self.clock)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    };
+
+    use tokio::time::Instant;
+
+    use super::{Blocked, CompositeLimiter};
+    use crate::{
+        Gcra, RateLimiter, RetryAfter, SlidingWindowLog, TokenBucket,
+        clock::{Clock, MockClock},
+        test_util::ms,
+    };
+
+    /// A limiter whose `peek` allows every call that its `check` then refuses.
+    struct Inconsistent;
+
+    impl RateLimiter for Inconsistent {
+        fn check(&self, _: Instant, _: u32) -> Result<(), RetryAfter> {
+            Err(RetryAfter(ms(50)))
+        }
+
+        fn peek(&self, _: Instant, _: u32) -> Result<(), RetryAfter> {
+            Ok(())
+        }
+
+        fn is_idle(&self, _: Instant) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn blocked_calls_consume_nothing() {
+        let clock = MockClock::new();
+        let start = clock.now();
+        let endpoint_clock = clock.clone();
+        let symbol_clock = clock.clone();
+        // Five calls a second for the account, bursts of three per endpoint refilling one a
+        // second, and two calls per symbol in any rolling second.
+        let limits = CompositeLimiter::new()
+            .limit("account", TokenBucket::new(5, 5.0).clock(clock.clone()))
+            .per_key(
+                "endpoint",
+                |&(endpoint, _): &(&str, &str)| endpoint,
+                move || Gcra::new(1.0, 3).clock(endpoint_clock.clone()),
+            )
+            .per_key(
+                "symbol",
+                |&(_, symbol)| symbol,
+                move || SlidingWindowLog::new(2, ms(1000)).clock(symbol_clock.clone()),
+            )
+            .clock(clock);
+
+        let blocked = |limit, millis| {
+            Err(Blocked {
+                limit,
+                retry_after: ms(millis),
+            })
+        };
+        assert_eq!(limits.try_acquire(&("orders", "BTC"), 1), Ok(()));
+        assert_eq!(limits.try_acquire(&("orders", "BTC"), 1), Ok(()));
+        assert_eq!(
+            limits.try_acquire(&("orders", "BTC"), 1),
+            blocked("symbol", 1000)
+        );
+        // The blocked call didn't use up the third call of the endpoint's burst.
+        assert_eq!(limits.try_acquire(&("orders", "ETH"), 1), Ok(()));
+        assert_eq!(
+            limits.try_acquire(&("orders", "SOL"), 1),
+            blocked("endpoint", 1000)
+        );
+        // Nor did it count against "SOL", which still has both of its calls.
+        assert_eq!(limits.try_acquire(&("quotes", "SOL"), 1), Ok(()));
+        assert_eq!(limits.try_acquire(&("quotes", "SOL"), 1), Ok(()));
+        assert_eq!(
+            limits.try_acquire(&("quotes", "ETH"), 1),
+            blocked("account", 200)
+        );
+        // With several limits binding, the longest wait wins, then the first limit added.
+        assert_eq!(
+            limits.check(&("orders", "BTC"), start + ms(100), 1),
+            blocked("endpoint", 900)
+        );
+    }
+
+    #[test]
+    fn check_refused_after_peek_blocks_instead_of_panicking() {
+        let clock = MockClock::new();
+        let limits = CompositeLimiter::new()
+            .limit("account", TokenBucket::new(2, 1.0).clock(clock.clone()))
+            .limit("broken", Inconsistent)
+            .clock(clock);
+
+        let blocked = Err(Blocked {
+            limit: "broken",
+            retry_after: ms(50),
+        });
+        assert_eq!(limits.try_acquire(&(), 1), blocked);
+        assert_eq!(limits.try_acquire(&(), 1), blocked);
+        // The limits before the broken one still counted each call.
+        assert_eq!(
+            limits.try_acquire(&(), 1),
+            Err(Blocked {
+                limit: "account",
+                retry_after: ms(1000),
+            })
+        );
+    }
+
+    #[test]
+    fn prune_drops_idle_per_key_limiters() {
+        let clock = MockClock::new();
+        let start = clock.now();
+        let created = Arc::new(AtomicUsize::new(0));
+        let symbol_clock = clock.clone();
+        let counter = created.clone();
+        let limits = CompositeLimiter::new()
+            .per_key(
+                "symbol",
+                |&symbol: &&str| symbol,
+                move || {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    Gcra::new(1.0, 2).clock(symbol_clock.clone())
+                },
+            )
+            .clock(clock);
+
+        assert_eq!(limits.check(&"BTC", start, 1), Ok(()));
+        assert_eq!(limits.check(&"ETH", start, 2), Ok(()));
+        assert_eq!(created.load(Ordering::SeqCst), 2);
+
+        // "BTC" has paid its call off after a second, but "ETH" still owes one.
+        limits.prune(start + ms(1000));
+        assert_eq!(
+            limits.check(&"ETH", start + ms(1000), 2),
+            Err(Blocked {
+                limit: "symbol",
+                retry_after: ms(1000),
+            })
+        );
+        assert_eq!(created.load(Ordering::SeqCst), 2);
+        // "BTC" starts again with a new limiter, which allows the same as the old one would.
+        assert_eq!(limits.check(&"BTC", start + ms(1000), 2), Ok(()));
+        assert_eq!(created.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn acquire_waits_for_every_limit() {
+        let limits = CompositeLimiter::new()
+            .limit("account", TokenBucket::new(2, 2.0))
+            .per_key("symbol", |&symbol: &&str| symbol, || Gcra::new(1.0, 1));
+        let start = Instant::now();
+        let mut acquired = Vec::new();
+
+        for symbol in ["x", "x", "y", "y", "z", "w"] {
+            limits.acquire(&symbol, 1).await;
+            acquired.push((symbol, start.elapsed().as_millis()));
+        }
+
+        // Each symbol waits a second between calls, and once "z" has spent the account's burst,
+        // "w" waits for it to refill.
+        assert_eq!(
+            acquired,
+            [
+                ("x", 0),
+                ("x", 1000),
+                ("y", 1000),
+                ("y", 2000),
+                ("z", 2000),
+                ("w", 2500),
+            ]
+        );
+    }
+}
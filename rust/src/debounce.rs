@@ -0,0 +1,218 @@
+use std::future::Future;
+
+use tokio::{
+    sync::watch,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    Summary,
+    clock::{Clock, TokioClock},
+};
+
+/// Wraps a [`watch::Receiver`] so that its latest value is only handed to a handler once the
+/// sender has been quiet for `quiet`.
+///
+/// Every change restarts the quiet period, so a sender that never pauses would never emit. Setting
+/// a `max_wait` bounds that: once `max_wait` has passed since the first change of a burst, the
+/// latest value is emitted even if changes are still arriving.
+#[derive(Debug)]
+pub struct DebouncedReceiver<T, C = TokioClock> {
+    rx: watch::Receiver<T>,
+    quiet: Duration,
+    max_wait: Option<Duration>,
+    clock: C,
+}
+
+impl<T: Clone> DebouncedReceiver<T> {
+    /// Creates a debounced receiver that emits once the sender has been quiet for `quiet`.
+    pub fn new(rx: watch::Receiver<T>, quiet: Duration) -> Self {
+        Self {
+            rx,
+            quiet,
+            max_wait: None,
+            clock: TokioClock,
+        }
+    }
+}
+
+impl<T: Clone, C: Clock> DebouncedReceiver<T, C> {
+    /// Sets the clock that quiet periods are timed by. Defaults to [`TokioClock`].
+    pub fn clock<C2: Clock>(self, clock: C2) -> DebouncedReceiver<T, C2> {
+        DebouncedReceiver {
+            rx: self.rx,
+            quiet: self.quiet,
+            max_wait: self.max_wait,
+            clock,
+        }
+    }
+
+    /// Sets the longest a burst of changes can delay an emit for, measured from its first change.
+    pub fn max_wait(mut self, max_wait: Duration) -> Self {
+        self.max_wait = Some(max_wait);
+        self
+    }
+
+    /// Runs the debounce loop, awaiting `handler` with each emitted value, until the
+    /// [`watch::Sender`] is dropped.
+    ///
+    /// A value still waiting out its quiet period when the sender is dropped is flushed to the
+    /// handler straight away, since nothing else can arrive to restart the quiet period.
+    pub async fn run<F, Fut>(mut self, mut handler: F) -> Summary
+    where
+        F: FnMut(T) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        let mut summary = Summary::default();
+
+        loop {
+            // Idle until a value starts a new burst.
+            if self.rx.changed().await.is_err() {
+                return summary;
+            }
+            let max_deadline = self.max_wait.map(|max_wait| self.clock.now() + max_wait);
+            let quiet_deadline = |now: Instant| match max_deadline {
+                Some(max_deadline) => (now + self.quiet).min(max_deadline),
+                None => now + self.quiet,
+            };
+
+            let mut deadline = quiet_deadline(self.clock.now());
+            let mut closed = false;
+            while !closed {
+                tokio::select! {
+                    _ = self.clock.sleep_until(deadline) => break,
+                    changed = self.rx.changed() => match changed {
+                        Ok(()) => deadline = quiet_deadline(self.clock.now()),
+                        Err(_) => closed = true,
+                    }
+                }
+            }
+
+            let value = self.rx.borrow_and_update().clone();
+            handler(value).await;
+            summary.emitted += 1;
+            if closed {
+                summary.flushed_on_close = true;
+                return summary;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::time::Duration;
+
+    use super::DebouncedReceiver;
+    use crate::{
+        Summary, ThrottledReceiver,
+        test_util::{PAIRS, TICK_MS, assert_marbles, replay, replay_with_output},
+    };
+
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn debounce_emits_after_quiet_period() {
+        let result = replay(PAIRS, TICK_MS, |rx, recorder| {
+            DebouncedReceiver::new(rx, Duration::from_millis(100)).run(move |value| {
+                recorder.record(value);
+                async {}
+            })
+        })
+        .await;
+
+        // Each of "a" through "d" is followed by more than 100ms of quiet, but the burst from
+        // 2050ms to 2200ms keeps restarting the quiet period until "i".
+        let expected = concat!(
+            "----a-----------------------b----------- ",
+            "------------c-----------------------d--- ",
+            "------------i",
+        );
+
+        assert_marbles(&result, expected, TICK_MS);
+    }
+
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn debounce_max_wait_emits_during_burst() {
+        let result = replay(PAIRS, TICK_MS, |rx, recorder| {
+            DebouncedReceiver::new(rx, Duration::from_millis(100))
+                .max_wait(Duration::from_millis(125))
+                .run(move |value| {
+                    recorder.record(value);
+                    async {}
+                })
+        })
+        .await;
+
+        // The burst starting with "e" at 2050ms is cut off at 2175ms, emitting "h", and "i" then
+        // starts a new burst of its own.
+        let expected = concat!(
+            "----a-----------------------b----------- ",
+            "------------c-----------------------d--- ",
+            "-------h----i",
+        );
+
+        assert_marbles(&result, expected, TICK_MS);
+    }
+
+    /// The same burst that debounce collapses into "i" is split across two windows by the
+    /// throttle, which also emits "a", "b" and "d" on its own schedule rather than after a pause.
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn debounce_and_throttle_diverge_on_bursts() {
+        let debounced = replay(PAIRS, TICK_MS, |rx, recorder| {
+            DebouncedReceiver::new(rx, Duration::from_millis(1000)).run(move |value| {
+                recorder.record(value);
+                async {}
+            })
+        })
+        .await;
+        let throttled = replay(PAIRS, TICK_MS, |rx, recorder| {
+            ThrottledReceiver::new(rx, Duration::from_millis(1000)).run(move |value| {
+                recorder.record(value);
+                async {}
+            })
+        })
+        .await;
+
+        // Every gap in the timeline is shorter than 1000ms, so debounce only emits the last value.
+        assert_marbles(
+            &debounced,
+            concat!(
+                "---------------------------------------- ",
+                "---------------------------------------- ",
+                "---------------------------------------- ",
+                "--------i",
+            ),
+            TICK_MS,
+        );
+        assert_marbles(
+            &throttled,
+            concat!(
+                "a--------------------------------------- ",
+                "b--------------------------------------- ",
+                "d--------------------------------------- ",
+                "i",
+            ),
+            TICK_MS,
+        );
+    }
+
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn dropping_sender_flushes_pending_value() {
+        let (result, summary) = replay_with_output("ab-|", 100, |rx, recorder| {
+            DebouncedReceiver::new(rx, Duration::from_millis(1000)).run(move |value| {
+                recorder.record(value);
+                async {}
+            })
+        })
+        .await;
+
+        assert_marbles(&result, "---(b|)", 100);
+        assert_eq!(
+            summary,
+            Some(Summary {
+                emitted: 1,
+                flushed_on_close: true,
+                stats: None,
+            })
+        );
+    }
+}
@@ -0,0 +1,192 @@
+use tokio::{
+    sync::watch::{
+        self,
+        error::{RecvError, SendError},
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    clock::{Clock, TokioClock},
+    sequence::{Sequence, Stamp, StampingSender},
+};
+
+/// A value stamped by an [`EnvelopeSender`] with its sequence number and when it was sent.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Envelope<T> {
+    pub seq: u64,
+    pub sent_at: Instant,
+    pub value: T,
+}
+
+impl<T> Envelope<T> {
+    /// Stamps the envelope with the current time on `clock` as the time it was read.
+    pub fn deliver(self, clock: &impl Clock) -> Delivery<T> {
+        Delivery {
+            envelope: self,
+            read_at: clock.now(),
+        }
+    }
+}
+
+impl<T> Sequence for Envelope<T> {
+    fn sequence(&self) -> u64 {
+        self.seq
+    }
+}
+
+impl<T> Stamp<(Instant, T)> for Envelope<T> {
+    fn restamp(&mut self, seq: u64, (sent_at, value): (Instant, T)) {
+        self.seq = seq;
+        self.sent_at = sent_at;
+        self.value = value;
+    }
+}
+
+/// An [`Envelope`] along with when it was read on the receiving side.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Delivery<T> {
+    pub envelope: Envelope<T>,
+    pub read_at: Instant,
+}
+
+impl<T> Delivery<T> {
+    /// How long the value spent between being sent and being read.
+    pub fn latency(&self) -> Duration {
+        self.read_at
+            .saturating_duration_since(self.envelope.sent_at)
+    }
+}
+
+/// Wraps a [`watch::Sender`] to stamp each value with the next sequence number and the time it
+/// was sent.
+///
+/// The initial value of the channel has sequence number 0, so the first value sent is 1.
+#[derive(Debug)]
+pub struct EnvelopeSender<T, C = TokioClock> {
+    tx: StampingSender<Envelope<T>>,
+    clock: C,
+}
+
+impl<T, C: Clock> EnvelopeSender<T, C> {
+    /// Sets the clock that envelopes are stamped by. Defaults to [`TokioClock`].
+    pub fn clock<C2: Clock>(self, clock: C2) -> EnvelopeSender<T, C2> {
+        EnvelopeSender { tx: self.tx, clock }
+    }
+
+    /// Sends `value` in the next envelope, failing if every receiver has been dropped.
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        self.tx
+            .send((self.clock.now(), value))
+            .map_err(|SendError((_, value))| SendError(value))
+    }
+
+    /// Creates another receiver for the channel.
+    pub fn subscribe(&self) -> watch::Receiver<Envelope<T>> {
+        self.tx.subscribe()
+    }
+}
+
+/// Wraps a [`watch::Receiver`] of envelopes to hand out [`Delivery`]s stamped with when they were
+/// read.
+///
+/// Recipes like [`ThrottledReceiver`](crate::ThrottledReceiver) take the plain
+/// [`watch::Receiver`] instead, and their handlers can call [`Envelope::deliver`] themselves.
+#[derive(Debug)]
+pub struct EnvelopeReceiver<T, C = TokioClock> {
+    rx: watch::Receiver<Envelope<T>>,
+    clock: C,
+}
+
+impl<T: Clone> EnvelopeReceiver<T> {
+    /// Wraps a receiver created by [`channel`] or [`EnvelopeSender::subscribe`].
+    pub fn new(rx: watch::Receiver<Envelope<T>>) -> Self {
+        Self {
+            rx,
+            clock: TokioClock,
+        }
+    }
+}
+
+impl<T: Clone, C: Clock> EnvelopeReceiver<T, C> {
+    /// Sets the clock that deliveries are stamped by. Defaults to [`TokioClock`].
+    pub fn clock<C2: Clock>(self, clock: C2) -> EnvelopeReceiver<T, C2> {
+        EnvelopeReceiver { rx: self.rx, clock }
+    }
+
+    /// Waits for an envelope that hasn't been seen yet and delivers it, failing once the sender
+    /// has been dropped and the latest envelope has been seen.
+    pub async fn recv(&mut self) -> Result<Delivery<T>, RecvError> {
+        self.rx.changed().await?;
+        Ok(self.rx.borrow_and_update().clone().deliver(&self.clock))
+    }
+
+    /// Returns the wrapped receiver.
+    pub fn into_inner(self) -> watch::Receiver<Envelope<T>> {
+        self.rx
+    }
+}
+
+/// Creates a [`watch`] channel whose values are stamped with sequence numbers and send times.
+pub fn channel<T>(init: T) -> (EnvelopeSender<T>, watch::Receiver<Envelope<T>>) {
+    let (tx, rx) = StampingSender::channel(Envelope {
+        seq: 0,
+        sent_at: TokioClock.now(),
+        value: init,
+    });
+    (
+        EnvelopeSender {
+            tx,
+            clock: TokioClock,
+        },
+        rx,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::time::{self, Duration, Instant};
+
+    use super::{EnvelopeReceiver, channel};
+    use crate::clock::{Clock, MockClock};
+
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn delivery_measures_queue_latency() {
+        let (tx, rx) = channel("");
+        let mut rx = EnvelopeReceiver::new(rx);
+        let start = Instant::now();
+
+        tx.send("a").unwrap();
+        time::advance(Duration::from_millis(250)).await;
+        let delivery = rx.recv().await.unwrap();
+
+        assert_eq!(delivery.envelope.seq, 1);
+        assert_eq!(delivery.envelope.value, "a");
+        assert_eq!(delivery.envelope.sent_at, start);
+        assert_eq!(delivery.read_at, start + Duration::from_millis(250));
+        assert_eq!(delivery.latency(), Duration::from_millis(250));
+
+        drop(tx);
+        assert!(rx.recv().await.is_err());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn stamps_times_on_given_clock() {
+        let clock = MockClock::new();
+        let start = clock.now();
+        let (tx, rx) = channel("");
+        let tx = tx.clock(clock.clone());
+        let mut rx = EnvelopeReceiver::new(rx).clock(clock.clone());
+
+        clock.advance(Duration::from_millis(100));
+        tx.send("a").unwrap();
+        clock.advance(Duration::from_millis(250));
+        let delivery = rx.recv().await.unwrap();
+
+        assert_eq!(
+            delivery.envelope.sent_at,
+            start + Duration::from_millis(100)
+        );
+        assert_eq!(delivery.read_at, start + Duration::from_millis(350));
+    }
+}
@@ -0,0 +1,321 @@
+use std::{collections::HashMap, hash::Hash, sync::Mutex};
+
+use tokio::time::{Duration, Instant};
+
+use crate::{
+    RateLimiter, RetryAfter,
+    clock::{Clock, TokioClock},
+    limit, sync,
+};
+
+/// The parameters of the generic cell rate algorithm, shared by [`Gcra`] and [`KeyedGcra`].
+#[derive(Clone, Copy, Debug)]
+struct Cell {
+    /// How long a single unit of cost takes to be paid off.
+    emission: Duration,
+    burst: u32,
+}
+
+impl Cell {
+    fn new(rate: f64, burst: u32) -> Self {
+        assert!(burst > 0, "the limiter must allow a call");
+        assert!(
+            rate > 0.0 && rate.is_finite(),
+            "the limiter must allow calls at a positive rate"
+        );
+        Self {
+            emission: limit::per_unit(rate, burst),
+            burst,
+        }
+    }
+
+    /// Checks a call costing `cost` at `now` against the theoretical arrival time `tat`,
+    /// returning what it moves on to if the call is allowed.
+    fn check(&self, tat: Instant, now: Instant, cost: u32) -> Result<Instant, RetryAfter> {
+        assert!(
+            cost <= self.burst,
+            "a cost of {cost} can't be met by a burst of {}",
+            self.burst
+        );
+        let next = tat.max(now) + self.emission * cost;
+        // A call is allowed as long as it doesn't run further ahead of `now` than a full burst.
+        // That's compared as durations, since an `Instant` a full burst before `next` can be
+        // earlier than the platform can represent.
+        let ahead = next - now;
+        let burst = self.emission * self.burst;
+        if ahead > burst {
+            return Err(RetryAfter(ahead - burst));
+        }
+        Ok(next)
+    }
+}
+
+/// A rate limiter using the generic cell rate algorithm, which allows `rate` calls per second on
+/// average and up to `burst` at once.
+///
+/// It limits calls the same way as a [`TokenBucket`](crate::TokenBucket) of `burst` tokens, but
+/// its whole state is a single timestamp: when the calls allowed so far will have been paid off.
+/// That makes it cheap to keep one per key, as [`KeyedGcra`] does.
+#[derive(Debug)]
+pub struct Gcra<C = TokioClock> {
+    cell: Cell,
+    /// The theoretical arrival time of the next call.
+    tat: Mutex<Instant>,
+    clock: C,
+}
+
+impl Gcra {
+    /// Creates a limiter that allows `rate` calls per second, with bursts of up to `burst`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `burst` is zero, or `rate` isn't a positive number. It also panics if `rate` is
+    /// over a billion, or so low that a full burst would take more than a century to pay off.
+    pub fn new(rate: f64, burst: u32) -> Self {
+        Self {
+            cell: Cell::new(rate, burst),
+            // Any time that's already passed leaves the whole burst available.
+            tat: Mutex::new(Instant::now()),
+            clock: TokioClock,
+        }
+    }
+}
+
+impl<C: Clock> Gcra<C> {
+    /// Sets the clock that calls are timed by. Defaults to [`TokioClock`].
+    pub fn clock<C2: Clock>(self, clock: C2) -> Gcra<C2> {
+        Gcra {
+            cell: self.cell,
+            tat: Mutex::new(clock.now()),
+            clock,
+        }
+    }
+
+    /// Allows a call costing `cost` if it can go straight away, without waiting.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cost` is more than the burst.
+    pub fn try_acquire(&self, cost: u32) -> bool {
+        self.check(self.clock.now(), cost).is_ok()
+    }
+
+    /// Waits until a call costing `cost` is allowed. Nothing is counted until it is, so dropping
+    /// the future gives nothing up.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cost` is more than the burst.
+    pub async fn acquire(&self, cost: u32) {
+        limit::acquire(self, &self.clock, cost).await;
+    }
+}
+
+impl<C> RateLimiter for Gcra<C> {
+    fn check(&self, now: Instant, cost: u32) -> Result<(), RetryAfter> {
+        let mut tat = sync::lock(&self.tat);
+        *tat = self.cell.check(*tat, now, cost)?;
+        Ok(())
+    }
+
+    fn peek(&self, now: Instant, cost: u32) -> Result<(), RetryAfter> {
+        self.cell.check(*sync::lock(&self.tat), now, cost).map(drop)
+    }
+
+    fn is_idle(&self, now: Instant) -> bool {
+        *sync::lock(&self.tat) <= now
+    }
+}
+
+/// A [`Gcra`] limit applied to each key separately, keeping one timestamp per key.
+///
+/// Keys whose calls have all been paid off are indistinguishable from keys that were never seen,
+/// so [`prune`](KeyedGcra::prune) can drop them without changing what's allowed.
+#[derive(Debug)]
+pub struct KeyedGcra<K, C = TokioClock> {
+    cell: Cell,
+    tats: Mutex<HashMap<K, Instant>>,
+    clock: C,
+}
+
+impl<K> KeyedGcra<K> {
+    /// Creates a limiter that allows each key `rate` calls per second, with bursts of up to
+    /// `burst`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `burst` is zero, or `rate` isn't a positive number. It also panics if `rate` is
+    /// over a billion, or so low that a full burst would take more than a century to pay off.
+    pub fn new(rate: f64, burst: u32) -> Self {
+        Self {
+            cell: Cell::new(rate, burst),
+            tats: Mutex::new(HashMap::new()),
+            clock: TokioClock,
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone, C: Clock> KeyedGcra<K, C> {
+    /// Sets the clock that calls are timed by. Defaults to [`TokioClock`].
+    pub fn clock<C2: Clock>(self, clock: C2) -> KeyedGcra<K, C2> {
+        KeyedGcra {
+            cell: self.cell,
+            tats: self.tats,
+            clock,
+        }
+    }
+
+    /// How many keys have calls that haven't been paid off yet, or that haven't been pruned.
+    pub fn len(&self) -> usize {
+        sync::lock(&self.tats).len()
+    }
+
+    /// Whether no keys are being tracked.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The limit for `key`, to check it through the [`RateLimiter`] trait.
+    pub fn key(&self, key: K) -> GcraKey<'_, K, C> {
+        GcraKey { limiter: self, key }
+    }
+
+    /// Allows a call for `key` costing `cost` at `now`, or returns how long until it would be
+    /// allowed, as with [`RateLimiter::check`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cost` is more than the burst.
+    pub fn check(&self, key: &K, now: Instant, cost: u32) -> Result<(), RetryAfter> {
+        let mut tats = sync::lock(&self.tats);
+        // A key that isn't tracked has nothing left to pay off.
+        let tat = tats.get(key).copied().unwrap_or(now);
+        tats.insert(key.clone(), self.cell.check(tat, now, cost)?);
+        Ok(())
+    }
+
+    /// Works out what [`check`](KeyedGcra::check) would return for the same call, without
+    /// counting it if it's allowed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cost` is more than the burst.
+    pub fn peek(&self, key: &K, now: Instant, cost: u32) -> Result<(), RetryAfter> {
+        let tat = sync::lock(&self.tats).get(key).copied().unwrap_or(now);
+        self.cell.check(tat, now, cost).map(drop)
+    }
+
+    /// Waits until a call for `key` costing `cost` is allowed. Nothing is counted until it is,
+    /// so dropping the future gives nothing up.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cost` is more than the burst.
+    pub async fn acquire(&self, key: K, cost: u32) {
+        limit::acquire(&self.key(key), &self.clock, cost).await;
+    }
+
+    /// Forgets every key whose calls have all been paid off by `now`.
+    pub fn prune(&self, now: Instant) {
+        sync::lock(&self.tats).retain(|_, tat| *tat > now);
+    }
+}
+
+/// The limit of a single key of a [`KeyedGcra`], returned by [`KeyedGcra::key`].
+#[derive(Debug)]
+pub struct GcraKey<'a, K, C = TokioClock> {
+    limiter: &'a KeyedGcra<K, C>,
+    key: K,
+}
+
+impl<K: Hash + Eq + Clone, C: Clock> RateLimiter for GcraKey<'_, K, C> {
+    fn check(&self, now: Instant, cost: u32) -> Result<(), RetryAfter> {
+        self.limiter.check(&self.key, now, cost)
+    }
+
+    fn peek(&self, now: Instant, cost: u32) -> Result<(), RetryAfter> {
+        self.limiter.peek(&self.key, now, cost)
+    }
+
+    fn is_idle(&self, now: Instant) -> bool {
+        sync::lock(&self.limiter.tats)
+            .get(&self.key)
+            .is_none_or(|tat| *tat <= now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::time::Instant;
+
+    use super::{Gcra, KeyedGcra};
+    use crate::{
+        RateLimiter, RetryAfter,
+        clock::{Clock, MockClock},
+        test_util::ms,
+    };
+
+    #[test]
+    fn bursts_then_spaces_weighted_calls() {
+        let clock = MockClock::new();
+        let start = clock.now();
+        let gcra = Gcra::new(10.0, 3).clock(clock);
+
+        assert_eq!(gcra.check(start, 2), Ok(()));
+        assert_eq!(gcra.check(start, 2), Err(RetryAfter(ms(100))));
+        assert_eq!(gcra.check(start, 1), Ok(()));
+        // The burst is spent, so each unit of cost waits out 100ms.
+        assert_eq!(gcra.check(start + ms(50), 2), Err(RetryAfter(ms(150))));
+        assert_eq!(gcra.check(start + ms(200), 2), Ok(()));
+        // Idle time only ever earns back the burst.
+        assert_eq!(gcra.check(start + ms(10_000), 3), Ok(()));
+        assert_eq!(gcra.check(start + ms(10_000), 1), Err(RetryAfter(ms(100))));
+    }
+
+    #[test]
+    fn keys_are_limited_separately() {
+        let clock = MockClock::new();
+        let start = clock.now();
+        let gcra = KeyedGcra::new(1.0, 1).clock(clock);
+
+        assert_eq!(gcra.check(&"x", start, 1), Ok(()));
+        assert_eq!(gcra.key("x").check(start, 1), Err(RetryAfter(ms(1000))));
+        assert_eq!(gcra.key("y").check(start + ms(500), 1), Ok(()));
+        assert_eq!(gcra.len(), 2);
+
+        // "x" is paid off at 1000ms and "y" at 1500ms, after which they're forgotten.
+        gcra.prune(start + ms(1000));
+        assert_eq!(gcra.len(), 1);
+        assert_eq!(
+            gcra.check(&"y", start + ms(1000), 1),
+            Err(RetryAfter(ms(500)))
+        );
+        gcra.prune(start + ms(1500));
+        assert!(gcra.is_empty());
+    }
+
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn acquire_waits_for_the_rate() {
+        let gcra = KeyedGcra::new(4.0, 2);
+        let start = Instant::now();
+        let mut acquired = Vec::new();
+
+        for key in ["x", "x", "y", "x", "x", "y"] {
+            gcra.acquire(key, 1).await;
+            acquired.push((key, start.elapsed().as_millis()));
+        }
+
+        // "y" keeps its own burst, even while "x" is waiting on the rate.
+        assert_eq!(
+            acquired,
+            [
+                ("x", 0),
+                ("x", 0),
+                ("y", 0),
+                ("x", 250),
+                ("x", 500),
+                ("y", 500),
+            ]
+        );
+    }
+}
@@ -0,0 +1,108 @@
+use std::{
+    future::{self, Future},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use tokio::{
+    sync::mpsc,
+    task::{JoinError, JoinHandle},
+    time::Duration,
+};
+
+use crate::Summary;
+
+/// A handle to a throttled receiver running on its own task, returned by
+/// [`ThrottledReceiver::spawn`](crate::ThrottledReceiver::spawn).
+///
+/// Awaiting the handle waits for the receiver to finish, like awaiting a [`JoinHandle`].
+/// Dropping the handle detaches the task rather than stopping it.
+///
+/// The control methods are applied the next time the receiver's task runs, and are ignored once
+/// the receiver has finished.
+#[derive(Debug)]
+pub struct ThrottleHandle {
+    task: JoinHandle<Summary>,
+    commands: mpsc::UnboundedSender<Command>,
+}
+
+impl ThrottleHandle {
+    pub(crate) fn new(task: JoinHandle<Summary>, commands: mpsc::UnboundedSender<Command>) -> Self {
+        Self { task, commands }
+    }
+
+    /// Stops emitting values. Values keep being received while paused, and the latest one is
+    /// emitted as soon as the receiver is resumed.
+    pub fn pause(&self) {
+        self.send(Command::Pause);
+    }
+
+    /// Resumes emitting values after a [`pause`](ThrottleHandle::pause).
+    pub fn resume(&self) {
+        self.send(Command::Resume);
+    }
+
+    /// Changes the interval between emits, taking effect from the next window. The current
+    /// window still closes when it was going to.
+    pub fn set_interval(&self, interval: Duration) {
+        self.send(Command::SetInterval(interval));
+    }
+
+    /// Emits the latest value straight away if it hasn't been emitted yet, even while paused,
+    /// and starts a new window.
+    pub fn flush(&self) {
+        self.send(Command::Flush);
+    }
+
+    /// Stops the receiver cleanly, the same way as dropping the sender does, so a pending
+    /// trailing value is flushed before it returns.
+    pub fn stop(&self) {
+        self.send(Command::Stop);
+    }
+
+    /// Stops the receiver at its next `.await`, without flushing anything still pending.
+    pub fn abort(&self) {
+        self.task.abort();
+    }
+
+    /// Whether the receiver's task has finished.
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+
+    fn send(&self, command: Command) {
+        // The receiver has already finished if this fails, so there's nothing left to control.
+        let _ = self.commands.send(command);
+    }
+}
+
+impl Future for ThrottleHandle {
+    type Output = Result<Summary, JoinError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.task).poll(cx)
+    }
+}
+
+/// A request sent from a [`ThrottleHandle`] to its receiver.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Command {
+    Pause,
+    Resume,
+    SetInterval(Duration),
+    Flush,
+    Stop,
+}
+
+/// Waits for the next command, or forever if there's no handle or it has been dropped.
+pub(crate) async fn next_command(
+    commands: &mut Option<mpsc::UnboundedReceiver<Command>>,
+) -> Command {
+    if let Some(rx) = commands {
+        if let Some(command) = rx.recv().await {
+            return command;
+        }
+        *commands = None;
+    }
+    future::pending().await
+}
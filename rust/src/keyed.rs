@@ -0,0 +1,289 @@
+use std::{collections::HashMap, future::Future, hash::Hash};
+
+use tokio::{
+    sync::mpsc,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    Action, Summary, Throttle, ThrottleEdge,
+    clock::{Clock, TokioClock},
+    wheel::{TimerKey, TimerWheel},
+};
+
+/// How finely the timer wheel rounds up each key's deadlines.
+const TIMER_RESOLUTION: Duration = Duration::from_millis(1);
+
+#[derive(Debug)]
+struct Key<V> {
+    throttle: Throttle<V>,
+    last_value: Instant,
+    /// When the key's timer fires, and its entry in the wheel, if it has one.
+    timer: Option<(Instant, TimerKey)>,
+}
+
+/// Throttles `(key, value)` pairs from an [`mpsc::Receiver`] with a separate window per key, all
+/// from a single task.
+///
+/// Each key behaves like its own [`ThrottledReceiver`](crate::ThrottledReceiver): values for a key
+/// only overwrite other values for the same key, and windows open and close at each key's own
+/// cadence. A key is forgotten once its window has closed and no value has arrived for it within
+/// the `ttl`, so memory grows with the number of recently active keys rather than every key ever
+/// seen.
+///
+/// Every key has at most one timer, either for its window or its eviction, kept in a
+/// [`TimerWheel`] so that only the earliest of them is waited on. Deadlines are rounded up to the
+/// next millisecond.
+#[derive(Debug)]
+pub struct KeyedThrottle<K, V, C = TokioClock> {
+    rx: mpsc::Receiver<(K, V)>,
+    interval: Duration,
+    edge: ThrottleEdge,
+    ttl: Duration,
+    clock: C,
+    keys: HashMap<K, Key<V>>,
+    timers: TimerWheel<K>,
+}
+
+impl<K, V> KeyedThrottle<K, V> {
+    /// Creates a keyed throttle that emits at most once per `interval` for each key.
+    pub fn new(rx: mpsc::Receiver<(K, V)>, interval: Duration) -> Self {
+        Self {
+            rx,
+            interval,
+            edge: ThrottleEdge::default(),
+            ttl: Duration::ZERO,
+            clock: TokioClock,
+            keys: HashMap::new(),
+            timers: TimerWheel::new(Instant::now(), TIMER_RESOLUTION),
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone, V, C: Clock> KeyedThrottle<K, V, C> {
+    /// Sets which edges of each key's windows emit a value. Defaults to [`ThrottleEdge::Both`].
+    pub fn edge(mut self, edge: ThrottleEdge) -> Self {
+        self.edge = edge;
+        self
+    }
+
+    /// Sets how long after its last value an idle key is kept around. Defaults to zero, which
+    /// forgets a key as soon as its window closes.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Sets the clock that windows are timed by. Defaults to [`TokioClock`].
+    pub fn clock<C2: Clock>(self, clock: C2) -> KeyedThrottle<K, V, C2> {
+        KeyedThrottle {
+            rx: self.rx,
+            interval: self.interval,
+            edge: self.edge,
+            ttl: self.ttl,
+            // Nothing has been scheduled yet, so the wheel can start again from the new clock.
+            timers: TimerWheel::new(clock.now(), TIMER_RESOLUTION),
+            clock,
+            keys: self.keys,
+        }
+    }
+
+    /// Runs the throttle loop, awaiting `handler` with each emitted key and value, until every
+    /// [`mpsc::Sender`] is dropped.
+    ///
+    /// Every key's pending trailing value is flushed straight away once the senders are dropped.
+    pub async fn run<F, Fut>(mut self, mut handler: F) -> Summary
+    where
+        F: FnMut(K, V) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        let mut summary = Summary::default();
+
+        loop {
+            let next_timer = self.timers.next_deadline();
+            tokio::select! {
+                _ = self.clock.sleep_until(next_timer.unwrap_or_else(|| self.clock.now())),
+                    if next_timer.is_some() =>
+                {
+                    let now = self.clock.now();
+                    while let Some(key) = self.pop_due(now) {
+                        if let Some(value) = self.fire(now, &key) {
+                            handler(key.clone(), value).await;
+                            summary.emitted += 1;
+                            self.handled(self.clock.now(), &key);
+                        }
+                    }
+                }
+                pair = self.rx.recv() => {
+                    let Some((key, value)) = pair else {
+                        for (key, mut state) in self.keys.drain() {
+                            if let Some(value) = state.throttle.on_close() {
+                                handler(key, value).await;
+                                summary.emitted += 1;
+                                summary.flushed_on_close = true;
+                            }
+                        }
+                        return summary;
+                    };
+                    if let Some(value) = self.on_value(self.clock.now(), key.clone(), value) {
+                        handler(key.clone(), value).await;
+                        summary.emitted += 1;
+                        self.handled(self.clock.now(), &key);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Handles a value for `key` that arrived at `now`, returning it if it should be emitted
+    /// straight away.
+    fn on_value(&mut self, now: Instant, key: K, value: V) -> Option<V> {
+        let state = self.keys.entry(key.clone()).or_insert_with(|| Key {
+            throttle: Throttle::new(self.interval).edge(self.edge),
+            last_value: now,
+            timer: None,
+        });
+        state.last_value = now;
+        let value = match state.throttle.on_value(now, value) {
+            Action::Emit(value) => Some(value),
+            Action::Hold | Action::Discard => None,
+        };
+        self.reschedule(&key);
+        value
+    }
+
+    /// Removes and returns a key whose timer is due by `now`, if there is one.
+    fn pop_due(&mut self, now: Instant) -> Option<K> {
+        let key = self.timers.poll(now)?;
+        if let Some(state) = self.keys.get_mut(&key) {
+            state.timer = None;
+        }
+        Some(key)
+    }
+
+    /// Handles the timer of `key` firing, returning the value to emit at the end of its window,
+    /// if any. A key whose timer fires while it's idle has outlived its TTL and is evicted.
+    fn fire(&mut self, now: Instant, key: &K) -> Option<V> {
+        let state = self.keys.get_mut(key)?;
+        if state.throttle.next_deadline().is_none() {
+            self.keys.remove(key);
+            return None;
+        }
+        let value = state.throttle.on_deadline(now);
+        self.reschedule(key);
+        value
+    }
+
+    /// Reschedules the window of `key` after the handler of its last emit returned at `now`.
+    fn handled(&mut self, now: Instant, key: &K) {
+        if let Some(state) = self.keys.get_mut(key) {
+            state.throttle.on_handled(now);
+            self.reschedule(key);
+        }
+    }
+
+    /// Moves the timer of `key` to the end of its window, or to its eviction if it's idle.
+    fn reschedule(&mut self, key: &K) {
+        let Some(state) = self.keys.get_mut(key) else {
+            return;
+        };
+        let at = state
+            .throttle
+            .next_deadline()
+            .unwrap_or(state.last_value + self.ttl);
+        if state.timer.is_some_and(|(timer, _)| timer == at) {
+            return;
+        }
+        if let Some((_, previous)) = state.timer.take() {
+            self.timers.remove(previous);
+        }
+        state.timer = Some((at, self.timers.insert(at, key.clone())));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc;
+
+    use super::KeyedThrottle;
+    use crate::{
+        Summary,
+        clock::{Clock, MockClock},
+        test_util::{ScriptedSender, ms, replay_mpsc},
+    };
+
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn keys_are_throttled_independently() {
+        let script = ScriptedSender::new([
+            (0, ('x', "a")),
+            (300, ('x', "b")),
+            (500, ('y', "p")),
+            (700, ('y', "q")),
+            (900, ('y', "r")),
+            (1200, ('x', "c")),
+        ]);
+
+        let (emitted, summary) = replay_mpsc(script.close_at(5000), |rx, emits| {
+            KeyedThrottle::new(rx, ms(1000)).run(move |key, value| {
+                emits.push((key, value));
+                async {}
+            })
+        })
+        .await;
+
+        // "y" opens its own window at 500ms rather than landing in the one "x" opened at 0ms.
+        assert_eq!(
+            emitted,
+            [
+                (0, ('x', "a")),
+                (500, ('y', "p")),
+                (1000, ('x', "b")),
+                (1500, ('y', "r")),
+                (2000, ('x', "c")),
+            ]
+        );
+        assert_eq!(
+            summary,
+            Summary {
+                emitted: 5,
+                flushed_on_close: false,
+                stats: None,
+            }
+        );
+    }
+
+    #[test]
+    fn idle_keys_are_evicted_after_ttl() {
+        let (_tx, rx) = mpsc::channel(1);
+        // The timer wheel counts from when the clock was set, so this keeps deadlines on its
+        // ticks.
+        let clock = MockClock::new();
+        let start = clock.now();
+        let mut throttle = KeyedThrottle::new(rx, ms(1000)).ttl(ms(5000)).clock(clock);
+
+        assert_eq!(throttle.on_value(start, "x", 1), Some(1));
+        assert_eq!(throttle.on_value(start + ms(100), "y", 2), Some(2));
+        assert_eq!(throttle.on_value(start + ms(500), "y", 3), None);
+
+        // "x" goes idle when its window closes at 1000ms, and "y" once the window after its
+        // trailing emit closes at 2100ms.
+        assert_eq!(throttle.pop_due(start + ms(1000)), Some("x"));
+        assert_eq!(throttle.fire(start + ms(1000), &"x"), None);
+        assert_eq!(throttle.pop_due(start + ms(1100)), Some("y"));
+        assert_eq!(throttle.fire(start + ms(1100), &"y"), Some(3));
+        assert_eq!(throttle.pop_due(start + ms(2100)), Some("y"));
+        assert_eq!(throttle.fire(start + ms(2100), &"y"), None);
+        assert_eq!(throttle.keys.len(), 2);
+        assert_eq!(throttle.timers.len(), 2);
+
+        // Each key is evicted 5000ms after its last value.
+        assert_eq!(throttle.pop_due(start + ms(4999)), None);
+        assert_eq!(throttle.pop_due(start + ms(5000)), Some("x"));
+        assert_eq!(throttle.fire(start + ms(5000), &"x"), None);
+        assert!(!throttle.keys.contains_key("x"));
+        assert_eq!(throttle.pop_due(start + ms(5500)), Some("y"));
+        assert_eq!(throttle.fire(start + ms(5500), &"y"), None);
+        assert!(throttle.keys.is_empty());
+        assert!(throttle.timers.is_empty());
+    }
+}
@@ -0,0 +1,181 @@
+use std::sync::Mutex;
+
+use tokio::time::{Duration, Instant};
+
+use crate::{
+    RateLimiter, RetryAfter,
+    clock::{Clock, TokioClock},
+    limit, sync,
+};
+
+/// A leaky bucket that queues calls and releases them at a steady `rate` per second, without
+/// ever letting them through in a burst.
+///
+/// The bucket holds up to `capacity` units of cost at a time, and leaks one every `1 / rate`
+/// seconds. [`acquire`](LeakyBucket::acquire) queues a call and waits for its turn, turning it
+/// away if the bucket would overflow, while [`check`](RateLimiter::check) only allows a call
+/// that wouldn't have to queue at all.
+#[derive(Debug)]
+pub struct LeakyBucket<C = TokioClock> {
+    capacity: u32,
+    /// How long a single unit of cost takes to leak out.
+    per_unit: Duration,
+    /// When everything in the bucket will have leaked out.
+    drained: Mutex<Instant>,
+    clock: C,
+}
+
+impl LeakyBucket {
+    /// Creates an empty bucket that holds up to `capacity` units of cost and leaks `rate` of
+    /// them per second.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is zero, or `rate` isn't a positive number. It also panics if `rate`
+    /// is over a billion, or so low that a full bucket would take more than a century to drain.
+    pub fn new(capacity: u32, rate: f64) -> Self {
+        assert!(capacity > 0, "the bucket must be able to hold a call");
+        assert!(
+            rate > 0.0 && rate.is_finite(),
+            "the bucket must leak at a positive rate"
+        );
+        Self {
+            capacity,
+            per_unit: limit::per_unit(rate, capacity),
+            drained: Mutex::new(Instant::now()),
+            clock: TokioClock,
+        }
+    }
+}
+
+impl<C: Clock> LeakyBucket<C> {
+    /// Sets the clock that the bucket leaks by. Defaults to [`TokioClock`].
+    pub fn clock<C2: Clock>(self, clock: C2) -> LeakyBucket<C2> {
+        LeakyBucket {
+            capacity: self.capacity,
+            per_unit: self.per_unit,
+            drained: Mutex::new(clock.now()),
+            clock,
+        }
+    }
+
+    /// Allows a call costing `cost` if the bucket is empty, without queueing it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cost` is more than the bucket's capacity.
+    pub fn try_acquire(&self, cost: u32) -> bool {
+        self.check(self.clock.now(), cost).is_ok()
+    }
+
+    /// Queues a call costing `cost` and waits for its turn to leak out, or returns how long until
+    /// it would fit if the bucket is too full to take it.
+    ///
+    /// The call's place in the queue is kept from the moment it joins, so dropping the future
+    /// while it waits still holds up the calls queued behind it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cost` is more than the bucket's capacity.
+    pub async fn acquire(&self, cost: u32) -> Result<(), RetryAfter> {
+        let release = self.enqueue(self.clock.now(), cost)?;
+        self.clock.sleep_until(release).await;
+        Ok(())
+    }
+
+    /// Adds a call costing `cost` to the bucket at `now`, returning when it reaches the front of
+    /// the queue.
+    fn enqueue(&self, now: Instant, cost: u32) -> Result<Instant, RetryAfter> {
+        self.assert_fits(cost);
+        let mut drained = sync::lock(&self.drained);
+        let release = (*drained).max(now);
+        let drained_after = release + self.per_unit * cost;
+        // The bucket overflows if it would take longer than a full bucket to drain.
+        let overflow = drained_after.saturating_duration_since(now + self.per_unit * self.capacity);
+        if !overflow.is_zero() {
+            return Err(RetryAfter(overflow));
+        }
+        *drained = drained_after;
+        Ok(release)
+    }
+
+    /// Works out how long a call costing `cost` at `now` has to wait for a bucket that will have
+    /// drained at `drained` to empty.
+    fn wait(&self, drained: Instant, now: Instant, cost: u32) -> Result<(), RetryAfter> {
+        self.assert_fits(cost);
+        if drained > now {
+            return Err(RetryAfter(drained - now));
+        }
+        Ok(())
+    }
+
+    fn assert_fits(&self, cost: u32) {
+        assert!(
+            cost <= self.capacity,
+            "a cost of {cost} can't fit in a bucket of {}",
+            self.capacity
+        );
+    }
+}
+
+impl<C: Clock> RateLimiter for LeakyBucket<C> {
+    fn check(&self, now: Instant, cost: u32) -> Result<(), RetryAfter> {
+        let mut drained = sync::lock(&self.drained);
+        self.wait(*drained, now, cost)?;
+        *drained = now + self.per_unit * cost;
+        Ok(())
+    }
+
+    fn peek(&self, now: Instant, cost: u32) -> Result<(), RetryAfter> {
+        self.wait(*sync::lock(&self.drained), now, cost)
+    }
+
+    fn is_idle(&self, now: Instant) -> bool {
+        *sync::lock(&self.drained) <= now
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::{
+        task::JoinSet,
+        time::{Duration, Instant, sleep_until},
+    };
+
+    use super::LeakyBucket;
+    use crate::RetryAfter;
+
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn releases_queued_calls_at_a_steady_rate() {
+        let bucket = Arc::new(LeakyBucket::new(3, 10.0));
+        let start = Instant::now();
+        let mut calls = JoinSet::new();
+
+        // Five calls arrive at once, then a sixth once the first two have leaked out.
+        for arrival in [0, 0, 0, 0, 0, 250] {
+            let bucket = bucket.clone();
+            calls.spawn(async move {
+                sleep_until(start + Duration::from_millis(arrival)).await;
+                let result = bucket.acquire(1).await;
+                (arrival, result, start.elapsed().as_millis())
+            });
+        }
+
+        let mut released = calls.join_all().await;
+        released.sort_by_key(|&(arrival, result, at)| (arrival, result.is_err(), at));
+        // Only three fit in the bucket at once, and the rest are told when there'll be room.
+        assert_eq!(
+            released,
+            [
+                (0, Ok(()), 0),
+                (0, Ok(()), 100),
+                (0, Ok(()), 200),
+                (0, Err(RetryAfter(Duration::from_millis(100))), 0),
+                (0, Err(RetryAfter(Duration::from_millis(100))), 0),
+                (250, Ok(()), 300),
+            ]
+        );
+    }
+}
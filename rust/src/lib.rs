@@ -0,0 +1,45 @@
+//! Miscellaneous async patterns/recipes, packaged so they can be pulled in as a dependency
+//! rather than copy-pasted between services.
+
+mod batch;
+pub mod blocking;
+mod bucket;
+pub mod clock;
+mod coalesce;
+mod composite;
+mod debounce;
+pub mod envelope;
+mod gcra;
+mod handle;
+mod keyed;
+mod leaky;
+mod limit;
+mod machine;
+mod pacer;
+mod schedule;
+pub mod sequence;
+mod sliding;
+pub mod stream;
+mod summary;
+mod sync;
+#[cfg(test)]
+mod test_util;
+mod throttle;
+pub mod wheel;
+
+pub use batch::{BatchingReceiver, EmptyBatches};
+pub use bucket::TokenBucket;
+pub use coalesce::CoalescingThrottle;
+pub use composite::{Blocked, CompositeLimiter};
+pub use debounce::DebouncedReceiver;
+pub use gcra::{Gcra, GcraKey, KeyedGcra};
+pub use handle::ThrottleHandle;
+pub use keyed::KeyedThrottle;
+pub use leaky::LeakyBucket;
+pub use limit::{RateLimiter, RetryAfter};
+pub use machine::{Action, Throttle};
+pub use pacer::{Paced, Pacer, TooSoon};
+pub use schedule::Schedule;
+pub use sliding::{SlidingWindowCounter, SlidingWindowLog};
+pub use summary::{Summary, ThrottleStats};
+pub use throttle::{Emission, ThrottleEdge, ThrottledReceiver};
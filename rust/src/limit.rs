@@ -0,0 +1,219 @@
+use std::{error::Error, fmt};
+
+use tokio::time::{Duration, Instant};
+
+use crate::clock::Clock;
+
+/// Returned by [`RateLimiter::check`] when a call has to wait, with how long until it would be
+/// allowed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct RetryAfter(pub Duration);
+
+impl fmt::Display for RetryAfter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rate limited, retry after {:?}", self.0)
+    }
+}
+
+impl Error for RetryAfter {}
+
+/// A rate limiting algorithm, so callers can swap one for another.
+///
+/// Limiters are shared between tasks, so they keep their state behind a lock and are checked
+/// through `&self`. Time is passed in rather than read from a clock, which keeps them usable from
+/// synchronous code and easy to drive through a simulated timeline.
+pub trait RateLimiter {
+    /// Allows a call costing `cost` at `now` and counts it against the limit, or returns how long
+    /// until it would be allowed without counting it.
+    ///
+    /// # Panics
+    ///
+    /// Implementations panic if `cost` is more than they could ever allow at once.
+    fn check(&self, now: Instant, cost: u32) -> Result<(), RetryAfter>;
+
+    /// Works out what [`check`](RateLimiter::check) would return for the same call, without
+    /// counting it if it's allowed.
+    ///
+    /// Implementations have to keep the two consistent: once `peek` allows a call, a `check` of
+    /// the same call at the same `now` has to allow it too, as long as nothing else has been
+    /// checked in between. [`CompositeLimiter`](crate::CompositeLimiter) relies on it to only
+    /// count a call once every limit allows it.
+    ///
+    /// # Panics
+    ///
+    /// Implementations panic if `cost` is more than they could ever allow at once.
+    fn peek(&self, now: Instant, cost: u32) -> Result<(), RetryAfter>;
+
+    /// Whether every call counted so far has been paid off by `now`, leaving the limiter to
+    /// allow calls just as a new one would.
+    fn is_idle(&self, now: Instant) -> bool;
+}
+
+/// The longest a limiter can take to pay off as much cost as it holds, which keeps every deadline
+/// it works out far inside what an [`Instant`] can represent.
+const LONGEST_PAYOFF: Duration = Duration::from_secs(100 * 365 * 24 * 60 * 60);
+
+/// How long a single unit of cost takes to pay off at `rate` units per second, for a limiter that
+/// holds up to `capacity` of them.
+///
+/// # Panics
+///
+/// Panics if a unit would pay off in under a nanosecond, or `capacity` units would take more than
+/// a century.
+pub(crate) fn per_unit(rate: f64, capacity: u32) -> Duration {
+    let per_unit = Duration::try_from_secs_f64(rate.recip()).unwrap_or(Duration::MAX);
+    assert!(
+        per_unit >= Duration::from_nanos(1),
+        "a rate of {rate} per second is too fast to time to the nanosecond"
+    );
+    assert!(
+        per_unit
+            .checked_mul(capacity)
+            .is_some_and(|full| full <= LONGEST_PAYOFF),
+        "a rate of {rate} per second takes over a century to pay off {capacity}"
+    );
+    per_unit
+}
+
+/// Checks `limiter` until it allows `cost`, sleeping on `clock` for as long as it asks to in
+/// between.
+///
+/// Nothing is counted until the call is allowed, so dropping the future gives nothing up.
+pub(crate) async fn acquire(limiter: &impl RateLimiter, clock: &impl Clock, cost: u32) {
+    loop {
+        let now = clock.now();
+        match limiter.check(now, cost) {
+            Ok(()) => return,
+            Err(RetryAfter(wait)) => clock.sleep_until(now + wait).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::time::{Duration, Instant};
+
+    use super::{RateLimiter, RetryAfter};
+    use crate::{
+        Gcra, LeakyBucket, SlidingWindowCounter, SlidingWindowLog, TokenBucket,
+        clock::{Clock, MockClock},
+    };
+
+    /// Checks a call costing one at each of `times` (in ms after `start`), returning how long
+    /// each one was told to wait, or zero if it was allowed.
+    fn waits(limiter: &impl RateLimiter, start: Instant, times: &[u64]) -> Vec<u128> {
+        times
+            .iter()
+            .map(|&time| {
+                match limiter.check(start + Duration::from_millis(time), 1) {
+                    Ok(()) => Duration::ZERO,
+                    Err(RetryAfter(wait)) => wait,
+                }
+                .as_millis()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn limiters_can_be_swapped() {
+        let clock = MockClock::new();
+        let start = clock.now();
+        let times = [0, 0, 0, 50, 100, 250, 260];
+
+        // A burst of two, refilling one every 100ms: the token bucket and GCRA agree exactly.
+        let bucket = TokenBucket::new(2, 10.0).clock(clock.clone());
+        let gcra = Gcra::new(10.0, 2).clock(clock.clone());
+        assert_eq!(waits(&bucket, start, &times), [0, 0, 100, 50, 0, 0, 40]);
+        assert_eq!(waits(&gcra, start, &times), [0, 0, 100, 50, 0, 0, 40]);
+
+        // Peeking doesn't count the call, so it keeps agreeing with the next check.
+        let at = start + Duration::from_millis(300);
+        for limiter in [&bucket as &dyn RateLimiter, &gcra] {
+            assert_eq!(limiter.peek(at, 1), Ok(()));
+            assert_eq!(limiter.peek(at, 1), Ok(()));
+            assert_eq!(limiter.check(at, 1), Ok(()));
+            assert_eq!(
+                limiter.peek(at, 1),
+                Err(RetryAfter(Duration::from_millis(100)))
+            );
+        }
+
+        // The leaky bucket doesn't burst at all, and spaces every call 100ms apart.
+        let leaky = LeakyBucket::new(2, 10.0).clock(clock);
+        assert_eq!(waits(&leaky, start, &times), [0, 100, 100, 50, 0, 0, 90]);
+    }
+
+    #[test]
+    fn limiters_are_idle_once_paid_off() {
+        let clock = MockClock::new();
+        let start = clock.now();
+        let bucket = TokenBucket::new(2, 10.0).clock(clock.clone());
+        let gcra = Gcra::new(10.0, 2).clock(clock.clone());
+        let leaky = LeakyBucket::new(2, 10.0).clock(clock.clone());
+        let log = SlidingWindowLog::new(2, Duration::from_millis(200)).clock(clock.clone());
+        let counter = SlidingWindowCounter::new(2, Duration::from_millis(200)).clock(clock);
+
+        // The buckets and GCRA pay a call off in 100ms, the log once it leaves the 200ms window,
+        // and the counter once its fixed window has stopped being the previous one too.
+        for (limiter, paid_off) in [
+            (&bucket as &dyn RateLimiter, 100),
+            (&gcra, 100),
+            (&leaky, 100),
+            (&log, 200),
+            (&counter, 400),
+        ] {
+            assert!(limiter.is_idle(start));
+            limiter.check(start, 1).unwrap();
+            assert!(!limiter.is_idle(start + Duration::from_millis(paid_off - 1)));
+            assert!(limiter.is_idle(start + Duration::from_millis(paid_off)));
+        }
+    }
+
+    #[test]
+    fn extreme_rates_stay_exact() {
+        let clock = MockClock::new();
+        let start = clock.now();
+        let times = [0, 0, 0];
+
+        // A billion a second pays a call off every nanosecond.
+        let bucket = TokenBucket::new(2, 1e9).clock(clock.clone());
+        let gcra = Gcra::new(1e9, 2).clock(clock.clone());
+        let leaky = LeakyBucket::new(2, 1e9).clock(clock.clone());
+        for limiter in [&bucket as &dyn RateLimiter, &gcra, &leaky] {
+            limiter.check(start, 1).unwrap();
+            assert!(limiter.check(start, 2).is_err());
+            assert_eq!(limiter.check(start + Duration::from_nanos(1), 2), Ok(()));
+        }
+        assert_eq!(bucket.available(), 0);
+
+        // Once every ten years, a full burst of three still pays off within a century.
+        let rate = 1.0 / (10.0 * 365.0 * 24.0 * 60.0 * 60.0);
+        let ten_years = 10 * 365 * 24 * 60 * 60 * 1000;
+        let bucket = TokenBucket::new(3, rate).clock(clock.clone());
+        let gcra = Gcra::new(rate, 3).clock(clock.clone());
+        assert_eq!(waits(&bucket, start, &times), [0, 0, 0]);
+        assert_eq!(waits(&gcra, start, &times), [0, 0, 0]);
+        assert_eq!(waits(&bucket, start, &[0]), [ten_years]);
+        assert_eq!(waits(&gcra, start, &[0]), [ten_years]);
+        let leaky = LeakyBucket::new(3, rate).clock(clock);
+        assert_eq!(waits(&leaky, start, &times), [0, ten_years, ten_years]);
+    }
+
+    #[test]
+    #[should_panic = "too fast"]
+    fn rate_over_a_billion_panics() {
+        TokenBucket::new(1, 1e10);
+    }
+
+    #[test]
+    #[should_panic = "over a century"]
+    fn tiny_rate_panics() {
+        Gcra::new(1e-300, 1);
+    }
+
+    #[test]
+    #[should_panic = "over a century"]
+    fn full_bucket_over_a_century_panics() {
+        LeakyBucket::new(u32::MAX, 1e-9);
+    }
+}
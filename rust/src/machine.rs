@@ -0,0 +1,355 @@
+//! The throttle's decisions as a state machine that never reads the clock or touches a channel.
+//!
+//! [`Throttle`] is told when values arrive and what time it is, and answers with what to emit and
+//! when it next needs to be woken up. [`ThrottledReceiver`](crate::ThrottledReceiver) and
+//! [`RateLimitExt::throttle`](crate::stream::RateLimitExt::throttle) drive it from tokio, but a
+//! thread, an event loop or a test that makes up its own instants can drive it just the same.
+
+use std::time::SystemTime;
+
+use tokio::time::{Duration, Instant};
+
+use crate::{
+    ThrottleEdge,
+    schedule::{Cadence, Schedule},
+};
+
+/// What to do with a value passed to [`Throttle::on_value`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Action<T> {
+    /// Emit the value straight away.
+    Emit(T),
+    /// The throttle is holding on to the value, replacing any value it held before, until the
+    /// window closes or the throttle is resumed or flushed.
+    Hold,
+    /// The value arrived during a leading-only window and is dropped.
+    Discard,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Window {
+    /// When the window was opened, used to reschedule its end once the handler returns.
+    opened: Instant,
+    end: Instant,
+}
+
+/// The throttle semantics of [`ThrottledReceiver`](crate::ThrottledReceiver), driven by explicit
+/// instants instead of a runtime.
+///
+/// Feed it every value with [`on_value`](Throttle::on_value), and call
+/// [`on_deadline`](Throttle::on_deadline) once [`next_deadline`](Throttle::next_deadline) has
+/// passed. Every instant passed in must be no earlier than the previous one.
+///
+/// Handlers are assumed to take no time. A driver that waits on a slow handler calls
+/// [`on_handled`](Throttle::on_handled) once it returns, so the next window is scheduled from
+/// then.
+#[derive(Debug)]
+pub struct Throttle<T> {
+    interval: Duration,
+    edge: ThrottleEdge,
+    cadence: Cadence,
+    /// The current window, or `None` while idle.
+    window: Option<Window>,
+    /// The latest value that arrived since the last emit.
+    pending: Option<T>,
+    paused: bool,
+}
+
+impl<T> Throttle<T> {
+    /// Creates a throttle that emits at most once per `interval`.
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            edge: ThrottleEdge::default(),
+            cadence: Cadence::new(Schedule::default(), interval),
+            window: None,
+            pending: None,
+            paused: false,
+        }
+    }
+
+    /// Sets which edges of each window emit a value. Defaults to [`ThrottleEdge::Both`].
+    pub fn edge(mut self, edge: ThrottleEdge) -> Self {
+        self.edge = edge;
+        self
+    }
+
+    /// Sets how the end of each window is scheduled. Defaults to [`Schedule::FixedDelay`].
+    pub fn schedule(mut self, schedule: Schedule) -> Self {
+        self.cadence = Cadence::new(schedule, self.interval);
+        self
+    }
+
+    /// Tells the throttle that the wall clock read `wall_time` at `now`, for a
+    /// [`Schedule::FixedRate`] aligned to the wall clock to line its grid up with. Until it's
+    /// told, an aligned grid starts at the first emit like an unaligned one.
+    pub fn set_wall_clock(&mut self, now: Instant, wall_time: SystemTime) {
+        self.cadence.set_wall_clock(now, wall_time);
+    }
+
+    /// The minimum time between two emits.
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// When [`on_deadline`](Throttle::on_deadline) next needs to be called, or `None` if the
+    /// throttle is idle or paused.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.window
+            .filter(|_| !self.paused)
+            .map(|window| window.end)
+    }
+
+    /// Handles a value that arrived at `now`.
+    pub fn on_value(&mut self, now: Instant, value: T) -> Action<T> {
+        if self.paused || self.window.is_some() {
+            if !self.paused && !self.edge.trailing() {
+                return Action::Discard;
+            }
+            self.pending = Some(value);
+            return Action::Hold;
+        }
+
+        // The value opens a new window.
+        self.open(now, now);
+        if self.edge.leading() {
+            return Action::Emit(value);
+        }
+        self.pending = Some(value);
+        Action::Hold
+    }
+
+    /// Closes the current window if its deadline has passed by `now`, returning the value to emit
+    /// at its trailing edge, if any. The window restarts after a trailing emit, and otherwise the
+    /// throttle goes back to idle.
+    pub fn on_deadline(&mut self, now: Instant) -> Option<T> {
+        let window = self.window?;
+        if self.paused || now < window.end {
+            return None;
+        }
+        let Some(value) = self.pending.take() else {
+            self.window = None;
+            return None;
+        };
+        self.open(window.end, now);
+        Some(value)
+    }
+
+    /// Reschedules the end of the current window from `now`, for when the handler of the last
+    /// emitted value returned at `now` rather than straight away.
+    pub fn on_handled(&mut self, now: Instant) {
+        if let Some(window) = &mut self.window {
+            window.end = self.cadence.window_end(window.opened, now);
+        }
+    }
+
+    /// Stops emitting values until [`resume`](Throttle::resume) is called. Values keep being held
+    /// in the meantime, whatever the edge.
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    /// Resumes emitting values after a [`pause`](Throttle::pause), returning the value that
+    /// arrived in the meantime, if any, to emit straight away.
+    pub fn resume(&mut self, now: Instant) -> Option<T> {
+        self.paused = false;
+        self.flush(now)
+    }
+
+    /// Returns the value that arrived since the last emit, if any, to emit straight away, even
+    /// while paused. Emitting it starts a new window.
+    pub fn flush(&mut self, now: Instant) -> Option<T> {
+        let value = self.pending.take()?;
+        self.open(now, now);
+        Some(value)
+    }
+
+    /// Changes the interval, taking effect from the next window.
+    pub fn set_interval(&mut self, interval: Duration) {
+        self.interval = interval;
+        self.cadence.set_interval(interval);
+    }
+
+    /// Handles the source of values closing, returning the pending trailing value to flush, if
+    /// any, since nothing else can arrive to overwrite it. The throttle goes back to idle.
+    pub fn on_close(&mut self) -> Option<T> {
+        self.window = None;
+        let pending = self.pending.take();
+        pending.filter(|_| self.edge.trailing())
+    }
+
+    fn open(&mut self, opened: Instant, now: Instant) {
+        if self.window.is_none() {
+            self.cadence.restart();
+        }
+        self.window = Some(Window {
+            opened,
+            end: self.cadence.window_end(opened, now),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::time::Instant;
+
+    use super::{Action, Throttle};
+    use crate::{
+        ThrottleEdge,
+        test_util::{PAIRS, SPREAD_PAIRS, TICK_MS, marble, ms},
+    };
+
+    /// Feeds the values of `marbles` to `throttle` at their ticks, closing every window whose
+    /// deadline falls at or before the next value, and asserts that the emitted values match the
+    /// `expected` marbles.
+    #[track_caller]
+    fn assert_simulated<'a>(
+        mut throttle: Throttle<&'a str>,
+        marbles: &'a str,
+        expected: &str,
+        tick_ms: u64,
+    ) {
+        let start = Instant::now();
+        let mut emitted = Vec::new();
+
+        for (time, value) in marble::parse(marbles, tick_ms).unwrap() {
+            let now = start + ms(time);
+            close_windows(&mut throttle, start, Some(now), &mut emitted);
+            match value {
+                "|" => {
+                    emitted.extend(throttle.on_close().map(|value| (time, value)));
+                    emitted.push((time, "|"));
+                }
+                value => {
+                    if let Action::Emit(value) = throttle.on_value(now, value) {
+                        emitted.push((time, value));
+                    }
+                }
+            }
+        }
+        close_windows(&mut throttle, start, None, &mut emitted);
+
+        if let Some(diff) = marble::diff(expected, &emitted, tick_ms) {
+            panic!("{diff}");
+        }
+    }
+
+    /// Closes every window of `throttle` that ends at or before `until`, or until it goes idle,
+    /// recording trailing emits in milliseconds since `start`.
+    fn close_windows<'a>(
+        throttle: &mut Throttle<&'a str>,
+        start: Instant,
+        until: Option<Instant>,
+        emitted: &mut Vec<(u64, &'a str)>,
+    ) {
+        while let Some(deadline) = throttle.next_deadline() {
+            if until.is_some_and(|until| deadline > until) {
+                return;
+            }
+            if let Some(value) = throttle.on_deadline(deadline) {
+                emitted.push(((deadline - start).as_millis() as u64, value));
+            }
+        }
+    }
+
+    #[test]
+    fn pairs_timeline_without_runtime() {
+        assert_simulated(
+            Throttle::new(ms(1000)),
+            PAIRS,
+            concat!(
+                "a--------------------------------------- ",
+                "b--------------------------------------- ",
+                "d--------------------------------------- ",
+                "i",
+            ),
+            TICK_MS,
+        );
+    }
+
+    #[test]
+    fn edges_without_runtime() {
+        let throttle = |edge| Throttle::new(ms(1000)).edge(edge);
+
+        assert_simulated(
+            throttle(ThrottleEdge::Leading),
+            SPREAD_PAIRS,
+            "a--------- --c------- -----e---- ------g",
+            100,
+        );
+        assert_simulated(
+            throttle(ThrottleEdge::Trailing),
+            SPREAD_PAIRS,
+            "---------- b--------- d--------- f--------- g",
+            100,
+        );
+        assert_simulated(
+            throttle(ThrottleEdge::Both),
+            SPREAD_PAIRS,
+            "a--------- b--------- d--------- f--------- g",
+            100,
+        );
+    }
+
+    #[test]
+    fn close_flushes_pending_trailing_value() {
+        assert_simulated(Throttle::new(ms(1000)), "a-b-|", "a---(b|)", 100);
+        assert_simulated(
+            Throttle::new(ms(1000)).edge(ThrottleEdge::Leading),
+            "ab|",
+            "a-|",
+            100,
+        );
+    }
+
+    #[test]
+    fn leading_only_window_discards_values() {
+        let start = Instant::now();
+        let mut throttle = Throttle::new(ms(1000)).edge(ThrottleEdge::Leading);
+
+        assert_eq!(throttle.on_value(start, "a"), Action::Emit("a"));
+        assert_eq!(throttle.on_value(start + ms(100), "b"), Action::Discard);
+        assert_eq!(throttle.on_deadline(start + ms(1000)), None);
+        assert_eq!(throttle.next_deadline(), None);
+        assert_eq!(throttle.on_value(start + ms(1100), "c"), Action::Emit("c"));
+    }
+
+    #[test]
+    fn slow_handler_pushes_back_window() {
+        let start = Instant::now();
+        let mut throttle = Throttle::new(ms(1000));
+
+        assert_eq!(throttle.on_value(start, "a"), Action::Emit("a"));
+        assert_eq!(throttle.next_deadline(), Some(start + ms(1000)));
+        throttle.on_handled(start + ms(300));
+        assert_eq!(throttle.next_deadline(), Some(start + ms(1300)));
+    }
+
+    #[test]
+    fn pause_holds_values_until_resumed_or_flushed() {
+        let start = Instant::now();
+        let mut throttle = Throttle::new(ms(1000));
+
+        assert_eq!(throttle.on_value(start, "a"), Action::Emit("a"));
+        throttle.pause();
+        assert_eq!(throttle.next_deadline(), None);
+        assert_eq!(throttle.on_value(start + ms(600), "b"), Action::Hold);
+        assert_eq!(throttle.on_deadline(start + ms(1000)), None);
+        assert_eq!(throttle.on_value(start + ms(1200), "c"), Action::Hold);
+
+        // Resuming emits the latest value and opens a new window from then.
+        assert_eq!(throttle.resume(start + ms(1500)), Some("c"));
+        assert_eq!(throttle.next_deadline(), Some(start + ms(2500)));
+
+        // The new interval applies from the window after the current one.
+        throttle.set_interval(ms(500));
+        assert_eq!(throttle.on_value(start + ms(1700), "d"), Action::Hold);
+        assert_eq!(throttle.on_deadline(start + ms(2500)), Some("d"));
+        assert_eq!(throttle.next_deadline(), Some(start + ms(3000)));
+
+        assert_eq!(throttle.on_value(start + ms(2600), "e"), Action::Hold);
+        assert_eq!(throttle.flush(start + ms(2700)), Some("e"));
+        assert_eq!(throttle.flush(start + ms(2800)), None);
+        assert_eq!(throttle.next_deadline(), Some(start + ms(3200)));
+    }
+}
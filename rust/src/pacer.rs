@@ -0,0 +1,195 @@
+use tokio::sync::{mpsc, watch};
+
+use crate::{
+    TokenBucket,
+    clock::{Clock, TokioClock},
+};
+
+/// What a [`Pacer`] does with a value sent too soon after the last one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TooSoon {
+    /// Wait until the value can be sent, holding up the producer.
+    #[default]
+    Wait,
+    /// Drop the value, handing it back to the producer.
+    Drop,
+}
+
+/// What became of a value handed to [`Pacer::send`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Paced<T> {
+    /// The value was sent.
+    Sent,
+    /// The value came too soon with [`TooSoon::Drop`], so it wasn't sent.
+    Dropped(T),
+}
+
+/// Wraps a [`watch::Sender`] or [`mpsc::Sender`] so that sends are spaced out to at most `rate`
+/// per second, throttling at the source rather than in the receiver.
+///
+/// Up to `burst` values can be sent back to back after a quiet spell, which defaults to one, so
+/// that sends are evenly spaced. The pacing is a [`TokenBucket`] of `burst` tokens, with each send
+/// taking one.
+///
+/// Dropping values that come too soon on a `watch` channel also drops the latest value if the
+/// producer then goes quiet, so a [`ThrottledReceiver`](crate::ThrottledReceiver), which emits
+/// the trailing value, is usually a better fit there.
+#[derive(Debug)]
+pub struct Pacer<S, C = TokioClock> {
+    sender: S,
+    rate: f64,
+    too_soon: TooSoon,
+    bucket: TokenBucket<C>,
+    clock: C,
+}
+
+impl<S> Pacer<S> {
+    /// Creates a pacer that sends at most `rate` values per second through `sender`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `rate` isn't a positive number, or is over a billion, or is so low that a send
+    /// would take more than a century to pay off.
+    pub fn new(sender: S, rate: f64) -> Self {
+        Self {
+            sender,
+            rate,
+            too_soon: TooSoon::default(),
+            bucket: TokenBucket::new(1, rate),
+            clock: TokioClock,
+        }
+    }
+}
+
+impl<S, C: Clock> Pacer<S, C> {
+    /// Sets how many values can be sent back to back after a quiet spell. Defaults to one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `burst` is zero, or the rate is so low that `burst` sends would take more than a
+    /// century to pay off.
+    pub fn burst(mut self, burst: u32) -> Self {
+        self.bucket = TokenBucket::new(burst, self.rate).clock(self.clock.clone());
+        self
+    }
+
+    /// Sets what happens to a value sent too soon. Defaults to [`TooSoon::Wait`].
+    pub fn too_soon(mut self, too_soon: TooSoon) -> Self {
+        self.too_soon = too_soon;
+        self
+    }
+
+    /// Sets the clock that sends are spaced out by. Defaults to [`TokioClock`].
+    pub fn clock<C2: Clock>(self, clock: C2) -> Pacer<S, C2> {
+        Pacer {
+            sender: self.sender,
+            rate: self.rate,
+            too_soon: self.too_soon,
+            bucket: self.bucket.clock(clock.clone()),
+            clock,
+        }
+    }
+
+    /// The wrapped sender.
+    pub fn sender(&self) -> &S {
+        &self.sender
+    }
+
+    /// Unwraps the sender.
+    pub fn into_inner(self) -> S {
+        self.sender
+    }
+
+    /// Waits for the value's turn, or hands it back if it came too soon with [`TooSoon::Drop`].
+    async fn pace<T>(&self, value: T) -> Result<T, Paced<T>> {
+        match self.too_soon {
+            TooSoon::Wait => self.bucket.acquire(1).await,
+            TooSoon::Drop if !self.bucket.try_acquire(1) => return Err(Paced::Dropped(value)),
+            TooSoon::Drop => {}
+        }
+        Ok(value)
+    }
+}
+
+impl<T, C: Clock> Pacer<watch::Sender<T>, C> {
+    /// Sends `value` once it's been long enough since the last send, failing if every receiver
+    /// has been dropped.
+    pub async fn send(&self, value: T) -> Result<Paced<T>, watch::error::SendError<T>> {
+        match self.pace(value).await {
+            Ok(value) => self.sender.send(value).map(|()| Paced::Sent),
+            Err(dropped) => Ok(dropped),
+        }
+    }
+}
+
+impl<T, C: Clock> Pacer<mpsc::Sender<T>, C> {
+    /// Sends `value` once it's been long enough since the last send and there's room in the
+    /// channel, failing if the receiver has been dropped.
+    pub async fn send(&self, value: T) -> Result<Paced<T>, mpsc::error::SendError<T>> {
+        match self.pace(value).await {
+            Ok(value) => self.sender.send(value).await.map(|()| Paced::Sent),
+            Err(dropped) => Ok(dropped),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        sync::{mpsc, watch},
+        time::{Duration, Instant, sleep_until},
+    };
+
+    use super::{Paced, Pacer, TooSoon};
+
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn waits_to_send_after_the_burst() {
+        let (tx, mut rx) = mpsc::channel(16);
+        let pacer = Pacer::new(tx, 10.0).burst(2);
+        let start = Instant::now();
+
+        let (_, received) = tokio::join!(
+            async move {
+                for value in 0..5 {
+                    assert_eq!(pacer.send(value).await, Ok(Paced::Sent));
+                }
+            },
+            async {
+                let mut received = Vec::new();
+                while let Some(value) = rx.recv().await {
+                    received.push((value, start.elapsed().as_millis()));
+                }
+                received
+            },
+        );
+
+        assert_eq!(received, [(0, 0), (1, 0), (2, 100), (3, 200), (4, 300)]);
+    }
+
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn drops_values_sent_too_soon() {
+        let (tx, rx) = watch::channel(0);
+        let pacer = Pacer::new(tx, 10.0).too_soon(TooSoon::Drop);
+        let start = Instant::now();
+        let mut paced = Vec::new();
+
+        for (time, value) in [(0, 1), (50, 2), (100, 3), (120, 4), (250, 5)] {
+            sleep_until(start + Duration::from_millis(time)).await;
+            paced.push(pacer.send(value).await.unwrap());
+            // Dropping a value never holds up the producer.
+            assert_eq!(start.elapsed(), Duration::from_millis(time));
+        }
+
+        assert_eq!(
+            paced,
+            [
+                Paced::Sent,
+                Paced::Dropped(2),
+                Paced::Sent,
+                Paced::Dropped(4),
+                Paced::Sent,
+            ]
+        );
+        assert_eq!(*rx.borrow(), 5);
+    }
+}
@@ -0,0 +1,212 @@
+use std::time::SystemTime;
+
+use tokio::time::{Duration, Instant, MissedTickBehavior};
+
+/// How the end of each window is scheduled after an emit.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Schedule {
+    /// Each window starts once the handler returns, so any time spent in the handler pushes every
+    /// later emit back by the same amount.
+    #[default]
+    FixedDelay,
+    /// Windows end on a grid of absolute deadlines `interval` apart, so time spent in the handler
+    /// doesn't accumulate. The grid starts at the emit that opens a window while the throttle is
+    /// idle, or on a wall-clock multiple of `interval` (e.g. every whole second) if
+    /// `align_to_wall_clock` is set, in which case the first window after an idle spell ends on
+    /// the next multiple and can be shorter than `interval`.
+    ///
+    /// `missed` decides what happens when the handler is still running at a deadline, with the
+    /// same meaning as it has for [`tokio::time::Interval`].
+    FixedRate {
+        missed: MissedTickBehavior,
+        align_to_wall_clock: bool,
+    },
+}
+
+/// Works out when each window ends according to a [`Schedule`].
+#[derive(Debug)]
+pub(crate) struct Cadence {
+    schedule: Schedule,
+    interval: Duration,
+    /// A point on the fixed-rate grid, set when a window is opened from idle.
+    origin: Option<Instant>,
+    /// The wall-clock time at an instant, which an aligned grid is lined up with.
+    wall_clock: Option<(Instant, SystemTime)>,
+}
+
+impl Cadence {
+    pub(crate) fn new(schedule: Schedule, interval: Duration) -> Self {
+        Self {
+            schedule,
+            interval,
+            origin: None,
+            wall_clock: None,
+        }
+    }
+
+    /// Records that the wall clock read `wall_time` at `now`.
+    pub(crate) fn set_wall_clock(&mut self, now: Instant, wall_time: SystemTime) {
+        self.wall_clock = Some((now, wall_time));
+    }
+
+    /// Changes the interval for the windows opened from now on. A fixed-rate grid restarts from
+    /// the next window that's opened.
+    pub(crate) fn set_interval(&mut self, interval: Duration) {
+        self.interval = interval;
+        self.origin = None;
+    }
+
+    /// Forgets the fixed-rate grid once the throttle has gone idle, so the next window starts a
+    /// fresh one rather than ending early on the old one. A grid aligned to the wall clock is kept.
+    pub(crate) fn restart(&mut self) {
+        if !matches!(
+            self.schedule,
+            Schedule::FixedRate {
+                align_to_wall_clock: true,
+                ..
+            }
+        ) {
+            self.origin = None;
+        }
+    }
+
+    /// Returns when the window opened at `opened` should end, where `now` is after the handler has
+    /// returned. `opened` is when a value arrived for a leading window, and the deadline of the
+    /// previous window for a trailing one.
+    pub(crate) fn window_end(&mut self, opened: Instant, now: Instant) -> Instant {
+        let Schedule::FixedRate {
+            missed,
+            align_to_wall_clock,
+        } = self.schedule
+        else {
+            return now + self.interval;
+        };
+
+        let interval = self.interval;
+        let wall_clock = self.wall_clock.filter(|_| align_to_wall_clock);
+        let origin = *self.origin.get_or_insert_with(|| match wall_clock {
+            Some((at, wall_time)) => {
+                let wall_time = if opened >= at {
+                    wall_time + (opened - at)
+                } else {
+                    wall_time - (at - opened)
+                };
+                let since_epoch = wall_time
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap_or_default();
+                aligned_origin(opened, since_epoch, interval)
+            }
+            None => opened,
+        });
+
+        let next = next_tick(origin, interval, opened);
+        if next > now {
+            return next;
+        }
+        match missed {
+            // Leave the deadline in the past so the next window closes straight away, and keep
+            // doing so until the grid catches up with the handler.
+            MissedTickBehavior::Burst => next,
+            MissedTickBehavior::Delay => {
+                self.origin = Some(now);
+                now + interval
+            }
+            MissedTickBehavior::Skip => next_tick(origin, interval, now),
+        }
+    }
+}
+
+/// Returns the first tick of the grid through `origin` that is strictly after `after`.
+fn next_tick(origin: Instant, interval: Duration, after: Instant) -> Instant {
+    let interval = interval.as_nanos().max(1);
+    let elapsed = after.saturating_duration_since(origin).as_nanos();
+    let ticks = elapsed / interval + 1;
+    origin + Duration::from_nanos((ticks * interval) as u64)
+}
+
+/// Returns the latest instant at or before `now` that falls on a wall-clock multiple of
+/// `interval`, given that `now` is `since_epoch` after the Unix epoch.
+fn aligned_origin(now: Instant, since_epoch: Duration, interval: Duration) -> Instant {
+    let offset = since_epoch.as_nanos() % interval.as_nanos().max(1);
+    now - Duration::from_nanos(offset as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::time::{Instant, MissedTickBehavior};
+
+    use super::{Cadence, Schedule, aligned_origin, next_tick};
+    use crate::test_util::ms;
+
+    fn fixed_rate(missed: MissedTickBehavior) -> Cadence {
+        Cadence::new(
+            Schedule::FixedRate {
+                missed,
+                align_to_wall_clock: false,
+            },
+            ms(1000),
+        )
+    }
+
+    #[test]
+    fn next_tick_is_strictly_after() {
+        let origin = Instant::now();
+        assert_eq!(next_tick(origin, ms(1000), origin), origin + ms(1000));
+        assert_eq!(
+            next_tick(origin, ms(1000), origin + ms(2500)),
+            origin + ms(3000)
+        );
+    }
+
+    #[test]
+    fn aligned_origin_falls_on_wall_clock_boundary() {
+        let now = Instant::now();
+        // 12.345s after the epoch, the last whole second was 345ms ago.
+        assert_eq!(aligned_origin(now, ms(12_345), ms(1000)), now - ms(345));
+        assert_eq!(aligned_origin(now, ms(12_345), ms(250)), now - ms(95));
+        assert_eq!(aligned_origin(now, ms(12_000), ms(1000)), now);
+    }
+
+    #[test]
+    fn fixed_delay_starts_after_handler() {
+        let start = Instant::now();
+        let mut cadence = Cadence::new(Schedule::FixedDelay, ms(1000));
+        assert_eq!(cadence.window_end(start, start + ms(300)), start + ms(1300));
+    }
+
+    #[test]
+    fn fixed_rate_handles_missed_ticks() {
+        let start = Instant::now();
+
+        // Finishing within the window keeps the grid regardless of the missed tick behavior.
+        let mut cadence = fixed_rate(MissedTickBehavior::Skip);
+        assert_eq!(cadence.window_end(start, start + ms(300)), start + ms(1000));
+        assert_eq!(
+            cadence.window_end(start + ms(1400), start + ms(1500)),
+            start + ms(2000)
+        );
+
+        // A handler that runs until 2300ms misses the ticks at 1000ms and 2000ms.
+        let mut cadence = fixed_rate(MissedTickBehavior::Burst);
+        assert_eq!(
+            cadence.window_end(start, start + ms(2300)),
+            start + ms(1000)
+        );
+        assert_eq!(
+            cadence.window_end(start + ms(1000), start + ms(2400)),
+            start + ms(2000)
+        );
+
+        let mut cadence = fixed_rate(MissedTickBehavior::Delay);
+        assert_eq!(
+            cadence.window_end(start, start + ms(2300)),
+            start + ms(3300)
+        );
+
+        let mut cadence = fixed_rate(MissedTickBehavior::Skip);
+        assert_eq!(
+            cadence.window_end(start, start + ms(2300)),
+            start + ms(3000)
+        );
+    }
+}
@@ -0,0 +1,116 @@
+use tokio::sync::watch::{self, error::SendError};
+
+/// A value that knows its position in the sequence of values sent on its channel.
+///
+/// Throttled receivers use the gaps between sequence numbers to tell how many values were
+/// coalesced between two emits, which a [`watch`] channel otherwise hides.
+pub trait Sequence {
+    /// The sequence number, which increases by one for every value sent.
+    fn sequence(&self) -> u64;
+}
+
+/// A value stamped with its sequence number by a [`SequencedSender`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sequenced<T> {
+    pub seq: u64,
+    pub value: T,
+}
+
+impl<T> Sequence for Sequenced<T> {
+    fn sequence(&self) -> u64 {
+        self.seq
+    }
+}
+
+/// A stamped value that a [`StampingSender`] updates in place with each value it sends.
+pub(crate) trait Stamp<T>: Sequence {
+    /// Replaces the value with `value`, sent with sequence number `seq`.
+    fn restamp(&mut self, seq: u64, value: T);
+}
+
+impl<T> Stamp<T> for Sequenced<T> {
+    fn restamp(&mut self, seq: u64, value: T) {
+        self.seq = seq;
+        self.value = value;
+    }
+}
+
+/// Wraps a [`watch::Sender`] of stamped values to stamp each value with the next sequence
+/// number, and whatever else `S` carries, as it's sent.
+#[derive(Debug)]
+pub(crate) struct StampingSender<S> {
+    tx: watch::Sender<S>,
+}
+
+impl<S> StampingSender<S> {
+    /// Creates a channel whose initial value is `init`.
+    pub(crate) fn channel(init: S) -> (Self, watch::Receiver<S>) {
+        let (tx, rx) = watch::channel(init);
+        (Self { tx }, rx)
+    }
+
+    /// Sends `value` with the next sequence number, failing if every receiver has been dropped.
+    pub(crate) fn send<T>(&self, value: T) -> Result<(), SendError<T>>
+    where
+        S: Stamp<T>,
+    {
+        if self.tx.is_closed() {
+            return Err(SendError(value));
+        }
+        self.tx.send_modify(|current| {
+            let seq = current.sequence() + 1;
+            current.restamp(seq, value);
+        });
+        Ok(())
+    }
+
+    /// Creates another receiver for the channel.
+    pub(crate) fn subscribe(&self) -> watch::Receiver<S> {
+        self.tx.subscribe()
+    }
+}
+
+/// Wraps a [`watch::Sender`] to stamp each value with the next sequence number as it's sent.
+///
+/// The initial value of the channel has sequence number 0, so the first value sent is 1.
+#[derive(Debug)]
+pub struct SequencedSender<T> {
+    tx: StampingSender<Sequenced<T>>,
+}
+
+impl<T> SequencedSender<T> {
+    /// Sends `value` with the next sequence number, failing if every receiver has been dropped.
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        self.tx.send(value)
+    }
+
+    /// Creates another receiver for the channel.
+    pub fn subscribe(&self) -> watch::Receiver<Sequenced<T>> {
+        self.tx.subscribe()
+    }
+}
+
+/// Creates a [`watch`] channel whose values are stamped with sequence numbers.
+pub fn channel<T>(init: T) -> (SequencedSender<T>, watch::Receiver<Sequenced<T>>) {
+    let (tx, rx) = StampingSender::channel(Sequenced {
+        seq: 0,
+        value: init,
+    });
+    (SequencedSender { tx }, rx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Sequenced, channel};
+
+    #[test]
+    fn stamps_consecutive_sequence_numbers() {
+        let (tx, rx) = channel("");
+        tx.send("a").unwrap();
+        tx.send("b").unwrap();
+        assert_eq!(*rx.borrow(), Sequenced { seq: 2, value: "b" });
+
+        drop(rx);
+        assert_eq!(tx.send("c").unwrap_err().0, "c");
+    }
+}
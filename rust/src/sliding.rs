@@ -0,0 +1,425 @@
+use std::{
+    collections::VecDeque,
+    sync::{Mutex, MutexGuard},
+};
+
+use tokio::time::{Duration, Instant};
+
+use crate::{
+    RateLimiter, RetryAfter,
+    clock::{Clock, TokioClock},
+    limit, sync,
+};
+
+/// A rate limiter that allows at most `limit` units of cost in any rolling `window`, keeping a
+/// log of when each call was allowed.
+///
+/// Unlike fixed windows, which allow a full burst either side of a boundary, every window of
+/// length `window` is checked, however it lines up. That takes memory for every call in the last
+/// `window`; [`SlidingWindowCounter`] approximates the same limit with two counters.
+#[derive(Debug)]
+pub struct SlidingWindowLog<C = TokioClock> {
+    limit: u32,
+    window: Duration,
+    state: Mutex<Log>,
+    clock: C,
+}
+
+#[derive(Debug, Default)]
+struct Log {
+    /// When each call in the last window was allowed, and its cost, oldest first.
+    calls: VecDeque<(Instant, u32)>,
+    /// The cost of every call in `calls`.
+    total: u32,
+}
+
+impl SlidingWindowLog {
+    /// Creates a limiter that allows `limit` units of cost in any rolling `window`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `limit` or `window` is zero.
+    pub fn new(limit: u32, window: Duration) -> Self {
+        assert_limit(limit, window);
+        Self {
+            limit,
+            window,
+            state: Mutex::new(Log::default()),
+            clock: TokioClock,
+        }
+    }
+}
+
+impl<C: Clock> SlidingWindowLog<C> {
+    /// Sets the clock that windows are timed by. Defaults to [`TokioClock`].
+    pub fn clock<C2: Clock>(self, clock: C2) -> SlidingWindowLog<C2> {
+        SlidingWindowLog {
+            limit: self.limit,
+            window: self.window,
+            state: self.state,
+            clock,
+        }
+    }
+
+    /// Allows a call costing `cost` if it fits in the window, without waiting.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cost` is more than the limit.
+    pub fn try_acquire(&self, cost: u32) -> bool {
+        self.check(self.clock.now(), cost).is_ok()
+    }
+
+    /// Waits until a call costing `cost` fits in the window. Nothing is counted until it does,
+    /// so dropping the future gives nothing up.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cost` is more than the limit.
+    pub async fn acquire(&self, cost: u32) {
+        limit::acquire(self, &self.clock, cost).await;
+    }
+
+    /// How long until a call costing `cost` would fit in the window, or zero if it already does.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cost` is more than the limit.
+    pub fn wait_time(&self, cost: u32) -> Duration {
+        let now = self.clock.now();
+        self.wait(&self.expired(now), now, cost)
+            .err()
+            .map_or(Duration::ZERO, |RetryAfter(wait)| wait)
+    }
+
+    /// Locks the log, dropping the calls that have left the window by `now`.
+    fn expired(&self, now: Instant) -> MutexGuard<'_, Log> {
+        let mut log = sync::lock(&self.state);
+        while let Some(&(at, cost)) = log.calls.front() {
+            if at + self.window > now {
+                break;
+            }
+            log.calls.pop_front();
+            log.total -= cost;
+        }
+        log
+    }
+
+    /// Works out how long a call costing `cost` at `now` has to wait for enough of the calls in
+    /// `log` to leave the window.
+    fn wait(&self, log: &Log, now: Instant, cost: u32) -> Result<(), RetryAfter> {
+        assert_cost(cost, self.limit);
+        // Added up in a wider type, since a total past `u32::MAX` is only further over the limit.
+        let total = u64::from(log.total) + u64::from(cost);
+        let mut excess = total.saturating_sub(u64::from(self.limit));
+        if excess == 0 {
+            return Ok(());
+        }
+        for &(at, freed) in &log.calls {
+            if u64::from(freed) >= excess {
+                return Err(RetryAfter(at + self.window - now));
+            }
+            excess -= u64::from(freed);
+        }
+        // `total` always matches the log, so the cost fits once every call has left it. Should
+        // that ever not hold, a full window is the longest any call in the log has left in it.
+        Err(RetryAfter(self.window))
+    }
+}
+
+impl<C: Clock> RateLimiter for SlidingWindowLog<C> {
+    fn check(&self, now: Instant, cost: u32) -> Result<(), RetryAfter> {
+        let mut log = self.expired(now);
+        self.wait(&log, now, cost)?;
+        log.calls.push_back((now, cost));
+        log.total += cost;
+        Ok(())
+    }
+
+    fn peek(&self, now: Instant, cost: u32) -> Result<(), RetryAfter> {
+        self.wait(&self.expired(now), now, cost)
+    }
+
+    fn is_idle(&self, now: Instant) -> bool {
+        self.expired(now).calls.is_empty()
+    }
+}
+
+/// A rate limiter that approximates [`SlidingWindowLog`] with a counter for the current fixed
+/// window and one for the window before it.
+///
+/// The previous window's count is weighted by how much of it still overlaps the rolling window
+/// ending now, as though its calls had been spread evenly across it. That can let a call through
+/// earlier than the log would, but never lets a fixed window's double burst through, and its
+/// state stays the same size however many calls are made.
+#[derive(Debug)]
+pub struct SlidingWindowCounter<C = TokioClock> {
+    limit: u32,
+    window: Duration,
+    state: Mutex<Counters>,
+    clock: C,
+}
+
+#[derive(Debug)]
+struct Counters {
+    /// When the current fixed window started.
+    start: Instant,
+    current: u32,
+    previous: u32,
+}
+
+impl SlidingWindowCounter {
+    /// Creates a limiter that allows roughly `limit` units of cost in any rolling `window`, with
+    /// its fixed windows starting now.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `limit` or `window` is zero.
+    pub fn new(limit: u32, window: Duration) -> Self {
+        assert_limit(limit, window);
+        Self {
+            limit,
+            window,
+            state: Mutex::new(Counters {
+                start: Instant::now(),
+                current: 0,
+                previous: 0,
+            }),
+            clock: TokioClock,
+        }
+    }
+}
+
+impl<C: Clock> SlidingWindowCounter<C> {
+    /// Sets the clock that windows are timed by, restarting the fixed windows from its current
+    /// time. Defaults to [`TokioClock`].
+    pub fn clock<C2: Clock>(self, clock: C2) -> SlidingWindowCounter<C2> {
+        let mut counters = sync::into_inner(self.state);
+        counters.start = clock.now();
+        SlidingWindowCounter {
+            limit: self.limit,
+            window: self.window,
+            state: Mutex::new(counters),
+            clock,
+        }
+    }
+
+    /// Allows a call costing `cost` if it fits in the window, without waiting.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cost` is more than the limit.
+    pub fn try_acquire(&self, cost: u32) -> bool {
+        self.check(self.clock.now(), cost).is_ok()
+    }
+
+    /// Waits until a call costing `cost` fits in the window. Nothing is counted until it does,
+    /// so dropping the future gives nothing up.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cost` is more than the limit.
+    pub async fn acquire(&self, cost: u32) {
+        limit::acquire(self, &self.clock, cost).await;
+    }
+
+    /// How long until a call costing `cost` would fit in the window, or zero if it already does.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cost` is more than the limit.
+    pub fn wait_time(&self, cost: u32) -> Duration {
+        let now = self.clock.now();
+        self.wait(&self.rolled(now), now, cost)
+            .err()
+            .map_or(Duration::ZERO, |RetryAfter(wait)| wait)
+    }
+
+    /// Locks the counters, moving them on to the fixed window that `now` falls in.
+    fn rolled(&self, now: Instant) -> MutexGuard<'_, Counters> {
+        let mut counters = sync::lock(&self.state);
+        let elapsed = now.saturating_duration_since(counters.start).as_nanos();
+        let windows = elapsed / self.window.as_nanos();
+        if windows > 0 {
+            counters.previous = if windows == 1 { counters.current } else { 0 };
+            counters.current = 0;
+            counters.start = now - Duration::from_nanos((elapsed % self.window.as_nanos()) as u64);
+        }
+        counters
+    }
+
+    /// Works out how long a call costing `cost` at `now` has to wait for the weighted count of
+    /// the previous window to fall far enough, assuming no other calls are made.
+    fn wait(&self, counters: &Counters, now: Instant, cost: u32) -> Result<(), RetryAfter> {
+        assert_cost(cost, self.limit);
+        let window = self.window.as_nanos();
+        // The call needs `previous * (window - elapsed) / window` to fall to `room`, which
+        // happens once `elapsed` reaches `window * (previous - room) / previous`.
+        let (start, previous, room) = match (self.limit - cost).checked_sub(counters.current) {
+            Some(room) => (counters.start, counters.previous, room),
+            // It won't fit until the current window has become the previous one.
+            None => (
+                counters.start + self.window,
+                counters.current,
+                self.limit - cost,
+            ),
+        };
+        let allowed_at = if previous <= room {
+            start
+        } else {
+            let (previous, room) = (u128::from(previous), u128::from(room));
+            start + Duration::from_nanos((window * (previous - room)).div_ceil(previous) as u64)
+        };
+        if allowed_at > now {
+            return Err(RetryAfter(allowed_at - now));
+        }
+        Ok(())
+    }
+}
+
+impl<C: Clock> RateLimiter for SlidingWindowCounter<C> {
+    fn check(&self, now: Instant, cost: u32) -> Result<(), RetryAfter> {
+        let mut counters = self.rolled(now);
+        self.wait(&counters, now, cost)?;
+        counters.current += cost;
+        Ok(())
+    }
+
+    fn peek(&self, now: Instant, cost: u32) -> Result<(), RetryAfter> {
+        self.wait(&self.rolled(now), now, cost)
+    }
+
+    fn is_idle(&self, now: Instant) -> bool {
+        let counters = self.rolled(now);
+        counters.current == 0 && counters.previous == 0
+    }
+}
+
+fn assert_limit(limit: u32, window: Duration) {
+    assert!(limit > 0, "the limiter must allow a call");
+    assert!(!window.is_zero(), "the window must have a length");
+}
+
+fn assert_cost(cost: u32, limit: u32) {
+    assert!(
+        cost <= limit,
+        "a cost of {cost} can't be met by a limit of {limit}"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::time::{Duration, Instant};
+
+    use super::{SlidingWindowCounter, SlidingWindowLog};
+    use crate::{
+        RateLimiter, RetryAfter,
+        clock::{Clock, MockClock},
+        test_util::ms,
+    };
+
+    /// Checks a burst of `count` calls costing one at `at`, returning how many were allowed.
+    fn burst(limiter: &impl RateLimiter, at: Instant, count: u32) -> u32 {
+        (0..count).filter(|_| limiter.check(at, 1).is_ok()).count() as u32
+    }
+
+    #[test]
+    fn log_blocks_the_double_burst_at_a_boundary() {
+        let clock = MockClock::new();
+        let start = clock.now();
+        let log = SlidingWindowLog::new(5, ms(1000)).clock(clock.clone());
+
+        // Fixed one-second windows would allow five at 990ms and five more at 1000ms, ten in
+        // 10ms. The rolling window ending at 1000ms still holds the first five.
+        assert_eq!(burst(&log, start + ms(990), 5), 5);
+        assert_eq!(burst(&log, start + ms(1000), 5), 0);
+        assert_eq!(log.check(start + ms(1000), 1), Err(RetryAfter(ms(990))));
+        assert_eq!(burst(&log, start + ms(1990), 5), 5);
+
+        clock.advance(ms(2500));
+        assert_eq!(log.wait_time(1), ms(490));
+    }
+
+    #[test]
+    fn log_waits_for_enough_weighted_calls_to_expire() {
+        let clock = MockClock::new();
+        let start = clock.now();
+        let log = SlidingWindowLog::new(6, ms(1000)).clock(clock);
+
+        assert_eq!(log.check(start, 2), Ok(()));
+        assert_eq!(log.check(start + ms(200), 3), Ok(()));
+        assert_eq!(log.check(start + ms(400), 1), Ok(()));
+        // Four units have to leave the window, so the first two calls have to expire.
+        assert_eq!(log.check(start + ms(500), 4), Err(RetryAfter(ms(700))));
+        assert_eq!(log.check(start + ms(1200), 4), Ok(()));
+    }
+
+    #[test]
+    fn log_counts_costs_past_u32_max_as_over_the_limit() {
+        let clock = MockClock::new();
+        let start = clock.now();
+        let log = SlidingWindowLog::new(u32::MAX, ms(1000)).clock(clock);
+
+        assert_eq!(log.check(start, u32::MAX - 1), Ok(()));
+        assert_eq!(
+            log.check(start + ms(100), u32::MAX),
+            Err(RetryAfter(ms(900)))
+        );
+        assert_eq!(log.check(start + ms(100), 1), Ok(()));
+        assert_eq!(
+            log.check(start + ms(1000), u32::MAX),
+            Err(RetryAfter(ms(100)))
+        );
+    }
+
+    #[test]
+    fn counter_blocks_the_double_burst_at_a_boundary() {
+        let clock = MockClock::new();
+        let start = clock.now();
+        let counter = SlidingWindowCounter::new(5, ms(1000)).clock(clock.clone());
+
+        assert_eq!(burst(&counter, start + ms(990), 5), 5);
+        assert_eq!(burst(&counter, start + ms(1000), 5), 0);
+        // The first window's five count as spread across it, so a fifth of them have left the
+        // rolling window by 1200ms. The log would make this call wait until 1990ms.
+        assert_eq!(counter.check(start + ms(1000), 1), Err(RetryAfter(ms(200))));
+        assert_eq!(counter.check(start + ms(1200), 1), Ok(()));
+        assert_eq!(counter.check(start + ms(1200), 1), Err(RetryAfter(ms(200))));
+        // A call that doesn't fit in the current window waits for the current window's calls to
+        // leave the rolling one entirely.
+        assert_eq!(
+            counter.check(start + ms(1500), 5),
+            Err(RetryAfter(ms(1500)))
+        );
+
+        // Once a whole window has passed without calls, the full limit is back.
+        clock.advance(ms(3000));
+        assert_eq!(counter.wait_time(5), Duration::ZERO);
+        assert_eq!(burst(&counter, start + ms(3000), 5), 5);
+    }
+
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn acquire_waits_for_the_rolling_window() {
+        let log = SlidingWindowLog::new(3, ms(1000));
+        let counter = SlidingWindowCounter::new(3, ms(1000));
+        let start = Instant::now();
+        let mut acquired = Vec::new();
+
+        for _ in 0..7 {
+            log.acquire(1).await;
+            acquired.push(start.elapsed().as_millis());
+        }
+        assert_eq!(acquired, [0, 0, 0, 1000, 1000, 1000, 2000]);
+
+        // Once the first fixed window is full, the counter lets another call through each time
+        // another third of that window's weight has left the rolling window, rounded up to
+        // tokio's millisecond timer.
+        acquired.clear();
+        let start = Instant::now();
+        for _ in 0..5 {
+            counter.acquire(1).await;
+            acquired.push(start.elapsed().as_millis());
+        }
+        assert_eq!(acquired, [0, 0, 0, 1334, 1667]);
+    }
+}
@@ -0,0 +1,405 @@
+//! The rate-limiting recipes as [`Stream`] adapters, for sources that aren't [`watch`] channels.
+//!
+//! Unlike a [`watch`] channel, a stream hands over every item, so the adapters keep the latest
+//! item themselves and drop the ones it replaces. When the source stream ends, any item still
+//! waiting to be emitted is flushed straight away, and then the adapter ends too.
+//!
+//! The adapters must be created within a tokio runtime, since they set up their timers eagerly.
+//!
+//! [`watch`]: tokio::sync::watch
+
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, ready},
+};
+
+use futures_core::Stream;
+use pin_project_lite::pin_project;
+use tokio::time::{
+    Duration, Instant, Interval, MissedTickBehavior, Sleep, interval_at, sleep_until,
+};
+
+use crate::{Action, Throttle, ThrottleEdge};
+
+/// Extension methods that rate limit any [`Stream`].
+pub trait RateLimitExt: Stream + Sized {
+    /// Emits at most one item per `interval`, with the same semantics as
+    /// [`ThrottledReceiver`](crate::ThrottledReceiver) for the given `edge`.
+    fn throttle(self, interval: Duration, edge: ThrottleEdge) -> ThrottleStream<Self> {
+        ThrottleStream {
+            stream: self,
+            sleep: sleep_until(Instant::now()),
+            throttle: Throttle::new(interval).edge(edge),
+            done: false,
+        }
+    }
+
+    /// Emits the latest item once the stream has been quiet for `quiet`, with the same semantics
+    /// as [`DebouncedReceiver`](crate::DebouncedReceiver).
+    fn debounce(self, quiet: Duration) -> Debounce<Self> {
+        Debounce {
+            stream: self,
+            sleep: sleep_until(Instant::now()),
+            quiet,
+            max_wait: None,
+            max_deadline: None,
+            pending: None,
+            done: false,
+        }
+    }
+
+    /// Emits the latest item on every tick of a fixed `period`, starting one `period` after the
+    /// adapter is first polled, if a new item arrived since the previous tick.
+    fn sample(self, period: Duration) -> Sample<Self> {
+        Sample {
+            stream: self,
+            interval: None,
+            period,
+            pending: None,
+            done: false,
+        }
+    }
+
+    /// Starts a timer for `duration` when an item arrives and no timer is running, and emits the
+    /// latest item once it fires.
+    ///
+    /// This is like a trailing-only [`throttle`](RateLimitExt::throttle), except that the window
+    /// isn't restarted after an emit, so the next window only opens once another item arrives.
+    fn audit(self, duration: Duration) -> Audit<Self> {
+        Audit {
+            stream: self,
+            sleep: sleep_until(Instant::now()),
+            duration,
+            timer_running: false,
+            pending: None,
+            done: false,
+        }
+    }
+}
+
+impl<S: Stream> RateLimitExt for S {}
+
+pin_project! {
+    /// Stream returned by [`RateLimitExt::throttle`].
+    #[must_use = "streams do nothing unless polled"]
+    pub struct ThrottleStream<S>
+    where
+        S: Stream,
+    {
+        #[pin]
+        stream: S,
+        #[pin]
+        sleep: Sleep,
+        throttle: Throttle<S::Item>,
+        done: bool,
+    }
+}
+
+impl<S: Stream> Stream for ThrottleStream<S> {
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<S::Item>> {
+        let mut this = self.project();
+
+        loop {
+            if *this.done {
+                return Poll::Ready(this.throttle.on_close());
+            }
+
+            if let Some(deadline) = this.throttle.next_deadline() {
+                if this.sleep.deadline() != deadline {
+                    this.sleep.as_mut().reset(deadline);
+                }
+                if this.sleep.as_mut().poll(cx).is_ready()
+                    && let Some(item) = this.throttle.on_deadline(Instant::now())
+                {
+                    return Poll::Ready(Some(item));
+                }
+            }
+
+            match ready!(this.stream.as_mut().poll_next(cx)) {
+                Some(item) => {
+                    if let Action::Emit(item) = this.throttle.on_value(Instant::now(), item) {
+                        return Poll::Ready(Some(item));
+                    }
+                }
+                None => *this.done = true,
+            }
+        }
+    }
+}
+
+pin_project! {
+    /// Stream returned by [`RateLimitExt::debounce`].
+    #[must_use = "streams do nothing unless polled"]
+    pub struct Debounce<S>
+    where
+        S: Stream,
+    {
+        #[pin]
+        stream: S,
+        #[pin]
+        sleep: Sleep,
+        quiet: Duration,
+        max_wait: Option<Duration>,
+        // When the current burst has to be emitted by, if `max_wait` is set.
+        max_deadline: Option<Instant>,
+        pending: Option<S::Item>,
+        done: bool,
+    }
+}
+
+impl<S: Stream> Debounce<S> {
+    /// Sets the longest a burst of items can delay an emit for, measured from its first item.
+    pub fn max_wait(mut self, max_wait: Duration) -> Self {
+        self.max_wait = Some(max_wait);
+        self
+    }
+}
+
+impl<S: Stream> Stream for Debounce<S> {
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<S::Item>> {
+        let mut this = self.project();
+
+        loop {
+            if *this.done {
+                return Poll::Ready(this.pending.take());
+            }
+
+            match this.stream.as_mut().poll_next(cx) {
+                Poll::Ready(Some(item)) => {
+                    let now = Instant::now();
+                    if this.pending.is_none() {
+                        *this.max_deadline = this.max_wait.map(|max_wait| now + max_wait);
+                    }
+                    let deadline = match *this.max_deadline {
+                        Some(max_deadline) => (now + *this.quiet).min(max_deadline),
+                        None => now + *this.quiet,
+                    };
+                    this.sleep.as_mut().reset(deadline);
+                    *this.pending = Some(item);
+                }
+                Poll::Ready(None) => *this.done = true,
+                Poll::Pending => {
+                    if this.pending.is_none() {
+                        return Poll::Pending;
+                    }
+                    ready!(this.sleep.as_mut().poll(cx));
+                    return Poll::Ready(this.pending.take());
+                }
+            }
+        }
+    }
+}
+
+pin_project! {
+    /// Stream returned by [`RateLimitExt::sample`].
+    #[must_use = "streams do nothing unless polled"]
+    pub struct Sample<S>
+    where
+        S: Stream,
+    {
+        #[pin]
+        stream: S,
+        // Created on the first poll, so that the ticks are relative to when sampling started.
+        interval: Option<Interval>,
+        period: Duration,
+        pending: Option<S::Item>,
+        done: bool,
+    }
+}
+
+impl<S: Stream> Stream for Sample<S> {
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<S::Item>> {
+        let mut this = self.project();
+        let period = *this.period;
+        let interval = this.interval.get_or_insert_with(|| {
+            let mut interval = interval_at(Instant::now() + period, period);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+            interval
+        });
+
+        loop {
+            if *this.done {
+                return Poll::Ready(this.pending.take());
+            }
+
+            match this.stream.as_mut().poll_next(cx) {
+                Poll::Ready(Some(item)) => *this.pending = Some(item),
+                Poll::Ready(None) => *this.done = true,
+                Poll::Pending => {
+                    ready!(interval.poll_tick(cx));
+                    if let Some(item) = this.pending.take() {
+                        return Poll::Ready(Some(item));
+                    }
+                }
+            }
+        }
+    }
+}
+
+pin_project! {
+    /// Stream returned by [`RateLimitExt::audit`].
+    #[must_use = "streams do nothing unless polled"]
+    pub struct Audit<S>
+    where
+        S: Stream,
+    {
+        #[pin]
+        stream: S,
+        #[pin]
+        sleep: Sleep,
+        duration: Duration,
+        timer_running: bool,
+        pending: Option<S::Item>,
+        done: bool,
+    }
+}
+
+impl<S: Stream> Stream for Audit<S> {
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<S::Item>> {
+        let mut this = self.project();
+
+        loop {
+            if *this.done {
+                return Poll::Ready(this.pending.take());
+            }
+
+            if *this.timer_running && this.sleep.as_mut().poll(cx).is_ready() {
+                *this.timer_running = false;
+                if let Some(item) = this.pending.take() {
+                    return Poll::Ready(Some(item));
+                }
+            }
+
+            match ready!(this.stream.as_mut().poll_next(cx)) {
+                Some(item) => {
+                    if !*this.timer_running {
+                        *this.timer_running = true;
+                        this.sleep.as_mut().reset(Instant::now() + *this.duration);
+                    }
+                    *this.pending = Some(item);
+                }
+                None => *this.done = true,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RateLimitExt;
+    use crate::{
+        ThrottleEdge,
+        test_util::{PAIRS, TICK_MS, assert_marbles, ms, replay_stream},
+    };
+
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn throttle_matches_throttled_receiver() {
+        let result = replay_stream(PAIRS, TICK_MS, |stream| {
+            stream.throttle(ms(1000), ThrottleEdge::Both)
+        })
+        .await;
+
+        assert_marbles(
+            &result,
+            concat!(
+                "a--------------------------------------- ",
+                "b--------------------------------------- ",
+                "d--------------------------------------- ",
+                "i",
+            ),
+            TICK_MS,
+        );
+    }
+
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn throttle_flushes_pending_item_when_stream_ends() {
+        let result = replay_stream("a-b-|", 100, |stream| {
+            stream.throttle(ms(1000), ThrottleEdge::Both)
+        })
+        .await;
+        assert_marbles(&result, "a---(b|)", 100);
+
+        let result = replay_stream("ab|", 100, |stream| {
+            stream.throttle(ms(1000), ThrottleEdge::Leading)
+        })
+        .await;
+        assert_marbles(&result, "a-|", 100);
+    }
+
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn debounce_matches_debounced_receiver() {
+        let result = replay_stream(PAIRS, TICK_MS, |stream| {
+            stream.debounce(ms(100)).max_wait(ms(125))
+        })
+        .await;
+
+        assert_marbles(
+            &result,
+            concat!(
+                "----a-----------------------b----------- ",
+                "------------c-----------------------d--- ",
+                "-------h----i",
+            ),
+            TICK_MS,
+        );
+    }
+
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn sample_emits_latest_on_each_tick() {
+        let result = replay_stream(PAIRS, TICK_MS, |stream| stream.sample(ms(1000))).await;
+
+        // Unlike the throttle, "a" isn't emitted straight away but only on the first tick, by
+        // which point it's been replaced by "b".
+        assert_marbles(
+            &result,
+            concat!(
+                "---------------------------------------- ",
+                "b--------------------------------------- ",
+                "d--------------------------------------- ",
+                "i",
+            ),
+            TICK_MS,
+        );
+    }
+
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn audit_only_restarts_timer_on_new_items() {
+        let audited = replay_stream(PAIRS, TICK_MS, |stream| stream.audit(ms(800))).await;
+        let throttled = replay_stream(PAIRS, TICK_MS, |stream| {
+            stream.throttle(ms(800), ThrottleEdge::Trailing)
+        })
+        .await;
+
+        // The timers opened by "a", "c" and "e" each run for 800ms before emitting the latest
+        // item.
+        assert_marbles(
+            &audited,
+            concat!(
+                "--------------------------------b------- ",
+                "---------------------------------------- ",
+                "d---------------------------------i",
+            ),
+            TICK_MS,
+        );
+        // The trailing throttle restarts its window as soon as it emits, so "c" lands in the
+        // window straight after "b".
+        assert_marbles(
+            &throttled,
+            concat!(
+                "--------------------------------b------- ",
+                "------------------------c--------------- ",
+                "----------------i",
+            ),
+            TICK_MS,
+        );
+    }
+}
@@ -0,0 +1,23 @@
+/// What a recipe did over its lifetime, returned once its sender is dropped.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Summary {
+    /// How many values were handed to the handler.
+    pub emitted: u64,
+    /// Whether a value that was still waiting to be emitted when the sender was dropped got
+    /// flushed to the handler on the way out.
+    pub flushed_on_close: bool,
+    /// How lossy the recipe was, if its values carry a sequence number to work that out from.
+    pub stats: Option<ThrottleStats>,
+}
+
+/// Cumulative counts of how many values a throttled receiver saw and how many it let through.
+///
+/// `received` counts every value sent while the receiver was running, including the ones it never
+/// saw because they were overwritten in the channel.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ThrottleStats {
+    pub received: u64,
+    pub emitted: u64,
+    /// Values that were overwritten by a later one instead of being emitted.
+    pub coalesced: u64,
+}
@@ -0,0 +1,38 @@
+//! Poison-tolerant locking for the state that the recipes share between threads.
+//!
+//! The state behind every lock in this crate is updated in one go and never left half-updated,
+//! so a panic elsewhere while a lock was held leaves it as usable as before. Carrying on keeps one
+//! panicking task from taking down every other user of the same limiter, channel or timer thread.
+
+use std::{
+    sync::{Condvar, Mutex, MutexGuard, PoisonError},
+    time::Duration,
+};
+
+/// Locks `mutex`, even if it was poisoned.
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Takes the state out of `mutex`, even if it was poisoned.
+pub(crate) fn into_inner<T>(mutex: Mutex<T>) -> T {
+    mutex.into_inner().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Waits on `condvar` until it's notified, even if the mutex behind `guard` was poisoned.
+pub(crate) fn wait<'a, T>(condvar: &Condvar, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+    condvar.wait(guard).unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Waits on `condvar` until it's notified or `timeout` passes, even if the mutex behind `guard`
+/// was poisoned.
+pub(crate) fn wait_timeout<'a, T>(
+    condvar: &Condvar,
+    guard: MutexGuard<'a, T>,
+    timeout: Duration,
+) -> MutexGuard<'a, T> {
+    condvar
+        .wait_timeout(guard, timeout)
+        .unwrap_or_else(PoisonError::into_inner)
+        .0
+}
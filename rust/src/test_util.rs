@@ -0,0 +1,255 @@
+//! Scripted timelines shared by the tests of every recipe, so they can be compared against the
+//! same inputs.
+//!
+//! Tests using these run on tokio's paused clock (`start_paused = true`), which jumps straight to
+//! the next pending timer whenever every task is idle. Replays therefore finish instantly and
+//! every timestamp is exact, so emits are asserted to the millisecond instead of within a
+//! tolerance.
+
+pub(crate) mod marble;
+mod scripted;
+
+use std::{
+    cell::RefCell,
+    future::{Future, poll_fn},
+    pin::Pin,
+    rc::Rc,
+};
+
+use futures_core::Stream;
+use tokio::{
+    sync::{mpsc, watch},
+    time::{Duration, Instant, sleep_until},
+};
+use tokio_stream::StreamExt;
+
+use crate::{
+    clock::TokioClock,
+    envelope::{self, Envelope},
+};
+
+pub(crate) use scripted::ScriptedSender;
+
+/// How long a replay keeps running after the last message is sent, in milliseconds, so that
+/// trailing emits have a chance to happen even behind a slow handler.
+const WRAP_UP_MS: u64 = 2500;
+
+/// The size of a tick in [`PAIRS`], which needs to be fine enough to place its burst of values.
+pub(crate) const TICK_MS: u64 = 25;
+
+/// The messages sent to the receiver, one second (40 ticks) per line.
+/// That is, "a" is sent at 0ms, "b" is sent at 600ms, "c" is sent at 1200ms, etc.
+/// For a receiver that only prints the latest message every second, this results in an
+/// output pattern where "a" and "b" are both printed, but "c" is skipped because "d" occurs
+/// before the second time boundary (2000ms).
+/// The rest of the messages test that the last message is still printed even if nothing
+/// explicitly triggers the handler after the third time boundary, since there are no
+/// messages after 3000ms.
+pub(crate) const PAIRS: &str = concat!(
+    "a-----------------------b--------------- ",
+    "--------c-----------------------d------- ",
+    "--efg-h-i",
+);
+
+/// A timeline where the last few values are sent well clear of any window boundary, so that
+/// the leading-only and trailing-only modes can be told apart from
+/// [`ThrottleEdge::Both`](crate::ThrottleEdge::Both). Each tick is 100ms, and each chunk is one
+/// second.
+pub(crate) const SPREAD_PAIRS: &str = "a-----b--- --c-----d- -----e-f-- ------g";
+
+/// A `(msg, sent_at, read_at)` tuple, in milliseconds since the start of the timeline.
+pub(crate) type Emitted = (String, u128, u128);
+
+/// Records the values a recipe emits, stamped with the time they were read.
+#[derive(Clone)]
+pub(crate) struct Recorder {
+    start: Instant,
+    received: Rc<RefCell<Vec<Emitted>>>,
+}
+
+impl Recorder {
+    fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            received: Rc::default(),
+        }
+    }
+
+    /// Differentiate between when the message was read vs sent. `sent_at` is when the message is
+    /// sent by the sender, `read_at` is when the recipe hands it to its handler.
+    pub(crate) fn record(&self, envelope: Envelope<String>) {
+        let delivery = envelope.deliver(&TokioClock);
+        let since_start = |at: Instant| (at - self.start).as_millis();
+        self.received.borrow_mut().push((
+            delivery.envelope.value,
+            since_start(delivery.envelope.sent_at),
+            since_start(delivery.read_at),
+        ));
+    }
+
+    /// Records the recipe returning as a `|`, matching the marble for the sender being dropped.
+    fn record_completion(&self) {
+        let at = self.start.elapsed().as_millis();
+        self.received.borrow_mut().push(("|".to_string(), at, at));
+    }
+}
+
+/// A duration of `ms` milliseconds, the unit every timeline is written in.
+pub(crate) fn ms(ms: u64) -> Duration {
+    Duration::from_millis(ms)
+}
+
+/// Scripts the messages of the `marbles` diagram, and returns when the replay is over, which is
+/// [`WRAP_UP_MS`] after the last message or the `|` that drops the sender.
+///
+/// Without a `|`, the sender is kept until the replay is over, so that the recipe is cut off
+/// rather than seeing its channel close.
+fn script(marbles: &str, tick_ms: u64) -> (ScriptedSender<String>, Instant) {
+    let script = ScriptedSender::marbles(marbles, tick_ms).map(str::to_string);
+    let end = script.end() + WRAP_UP_MS;
+    let over_at = Instant::now() + ms(end);
+    if marbles.contains('|') {
+        (script, over_at)
+    } else {
+        (script.close_at(end), over_at)
+    }
+}
+
+/// Sends each message in the `marbles` diagram at its tick into the receiver driven by `recipe`,
+/// and returns everything the recipe recorded.
+///
+/// Must be called from a runtime with a paused clock, otherwise the timeline plays out in real
+/// time and the recorded times drift.
+pub(crate) async fn replay<F, Fut>(marbles: &str, tick_ms: u64, recipe: F) -> Vec<Emitted>
+where
+    F: FnOnce(watch::Receiver<Envelope<String>>, Recorder) -> Fut,
+    Fut: Future,
+{
+    replay_with_output(marbles, tick_ms, recipe).await.0
+}
+
+/// Like [`replay`], but also returns the recipe's output if it returned before the replay ended.
+///
+/// A `|` in `marbles` drops the sender, and the recipe returning is recorded as a `|` too.
+pub(crate) async fn replay_with_output<F, Fut>(
+    marbles: &str,
+    tick_ms: u64,
+    recipe: F,
+) -> (Vec<Emitted>, Option<Fut::Output>)
+where
+    F: FnOnce(watch::Receiver<Envelope<String>>, Recorder) -> Fut,
+    Fut: Future,
+{
+    let (tx, rx) = envelope::channel(String::new());
+    let (script, over_at) = script(marbles, tick_ms);
+    let recorder = Recorder::new();
+
+    let output = tokio::select! {
+        // Checked first, so that a recipe cut off at the end of the replay doesn't get to see
+        // the sender being dropped.
+        biased;
+        _ = async {
+            script.play(tx).await;
+            sleep_until(over_at).await;
+        } => None,
+        output = recipe(rx, recorder.clone()) => {
+            recorder.record_completion();
+            Some(output)
+        },
+    };
+
+    (recorder.received.take(), output)
+}
+
+/// Like [`replay`], but yields each message from a stream of envelopes, transformed by
+/// `adapter`, and records every item the resulting stream yields.
+///
+/// A `|` in `marbles` ends the input stream, and the output stream ending is recorded as a `|`.
+pub(crate) async fn replay_stream<F, S>(marbles: &str, tick_ms: u64, adapter: F) -> Vec<Emitted>
+where
+    F: FnOnce(Pin<Box<dyn Stream<Item = Envelope<String>>>>) -> S,
+    S: Stream<Item = Envelope<String>>,
+{
+    let (script, over_at) = script(marbles, tick_ms);
+    let recorder = Recorder::new();
+    let mut seq = 0;
+    let input = script.into_stream().map(move |value| {
+        seq += 1;
+        Envelope {
+            seq,
+            sent_at: Instant::now(),
+            value,
+        }
+    });
+    let stream = adapter(Box::pin(input));
+    tokio::pin!(stream);
+
+    tokio::select! {
+        biased;
+        _ = sleep_until(over_at) => {},
+        _ = async {
+            while let Some(envelope) = poll_fn(|cx| stream.as_mut().poll_next(cx)).await {
+                recorder.record(envelope);
+            }
+            recorder.record_completion();
+        } => {},
+    }
+
+    recorder.received.take()
+}
+
+/// Collects what a recipe emits, stamped with when it was emitted, in milliseconds since the
+/// start of the timeline.
+pub(crate) struct Emits<E> {
+    start: Instant,
+    emitted: Rc<RefCell<Vec<(u128, E)>>>,
+}
+
+impl<E> Emits<E> {
+    pub(crate) fn push(&self, value: E) {
+        let at = self.start.elapsed().as_millis();
+        self.emitted.borrow_mut().push((at, value));
+    }
+}
+
+impl<E> Clone for Emits<E> {
+    fn clone(&self) -> Self {
+        Self {
+            start: self.start,
+            emitted: self.emitted.clone(),
+        }
+    }
+}
+
+/// Plays `script` into an [`mpsc`] channel read by the recipe driven by `recipe`, until both are
+/// done, and returns everything the recipe emitted along with its output.
+pub(crate) async fn replay_mpsc<T, E, F, Fut>(
+    script: ScriptedSender<T>,
+    recipe: F,
+) -> (Vec<(u128, E)>, Fut::Output)
+where
+    F: FnOnce(mpsc::Receiver<T>, Emits<E>) -> Fut,
+    Fut: Future,
+{
+    let (tx, rx) = mpsc::channel(16);
+    let emits = Emits {
+        start: Instant::now(),
+        emitted: Rc::default(),
+    };
+
+    let (_, output) = tokio::join!(script.play(tx), recipe(rx, emits.clone()));
+    (emits.emitted.take(), output)
+}
+
+/// Asserts that the values were emitted exactly at the ticks of the `expected` marbles, printing
+/// both timelines as marbles if they weren't.
+#[track_caller]
+pub(crate) fn assert_marbles(result: &[Emitted], expected: &str, tick_ms: u64) {
+    let actual: Vec<(u64, &str)> = result
+        .iter()
+        .map(|(msg, _, read_at)| (*read_at as u64, msg.as_str()))
+        .collect();
+    if let Some(diff) = marble::diff(expected, &actual, tick_ms) {
+        panic!("{diff}");
+    }
+}
@@ -0,0 +1,179 @@
+//! A small marble-diagram DSL for describing timelines, e.g. `"a-----b-----c-----d--(ef)"`.
+//!
+//! Every character is one tick of a configurable size:
+//!
+//! - `-` is a tick where nothing happens.
+//! - Any alphanumeric character is a value, sent or emitted at the start of its tick.
+//! - `|` is the sender being dropped in an input timeline, or the recipe returning in an
+//!   expected one. It's otherwise treated just like a value.
+//! - `(` and `)` group values that happen on the same tick, which is the tick of the `(`. As in
+//!   RxJS, the parentheses and every value inside them still take up a tick each.
+//! - Spaces are ignored, so long diagrams can be split into readable chunks, e.g. one per second.
+
+use std::fmt;
+
+/// Why a marble diagram failed to parse. Positions are byte offsets into the diagram.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum MarbleError {
+    UnexpectedChar { ch: char, position: usize },
+    NestedGroup { position: usize },
+    UnopenedGroup { position: usize },
+    UnclosedGroup { position: usize },
+}
+
+impl fmt::Display for MarbleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnexpectedChar { ch, position } => {
+                write!(f, "unexpected {ch:?} at position {position}")
+            }
+            Self::NestedGroup { position } => write!(f, "nested group at position {position}"),
+            Self::UnopenedGroup { position } => {
+                write!(f, "')' without a matching '(' at position {position}")
+            }
+            Self::UnclosedGroup { position } => {
+                write!(f, "'(' at position {position} is never closed")
+            }
+        }
+    }
+}
+
+impl std::error::Error for MarbleError {}
+
+/// Parses `marbles` into `(time_ms, value)` pairs, in the same shape as a scripted send schedule.
+pub(crate) fn parse(marbles: &str, tick_ms: u64) -> Result<Vec<(u64, &str)>, MarbleError> {
+    let mut events = Vec::new();
+    let mut tick = 0;
+    // The tick and position of the `(` of the group currently being parsed, if any.
+    let mut group: Option<(u64, usize)> = None;
+
+    for (position, ch) in marbles.char_indices() {
+        match ch {
+            ' ' => continue,
+            '-' => {}
+            '(' if group.is_some() => return Err(MarbleError::NestedGroup { position }),
+            '(' => group = Some((tick, position)),
+            ')' if group.is_none() => return Err(MarbleError::UnopenedGroup { position }),
+            ')' => group = None,
+            ch if ch.is_alphanumeric() || ch == '|' => {
+                let at = group.map_or(tick, |(group_tick, _)| group_tick);
+                events.push((at * tick_ms, &marbles[position..position + ch.len_utf8()]));
+            }
+            ch => return Err(MarbleError::UnexpectedChar { ch, position }),
+        }
+        tick += 1;
+    }
+
+    match group {
+        Some((_, position)) => Err(MarbleError::UnclosedGroup { position }),
+        None => Ok(events),
+    }
+}
+
+/// Renders `(time_ms, value)` pairs back into a marble diagram.
+///
+/// Times that don't fall on a tick are rounded down to one, and a value that would land inside
+/// the ticks taken up by a previous group is drawn straight after it, so the result is only exact
+/// for timelines that [`parse`] could have produced.
+pub(crate) fn render(events: &[(u64, &str)], tick_ms: u64) -> String {
+    let mut marbles = String::new();
+    let mut tick = 0;
+
+    for chunk in events.chunk_by(|(a, _), (b, _)| a / tick_ms == b / tick_ms) {
+        let at = chunk[0].0 / tick_ms;
+        while tick < at {
+            marbles.push('-');
+            tick += 1;
+        }
+        if let [(_, value)] = chunk {
+            marbles.push_str(value);
+            tick += 1;
+        } else {
+            marbles.push('(');
+            for (_, value) in chunk {
+                marbles.push_str(value);
+            }
+            marbles.push(')');
+            tick += chunk.len() as u64 + 2;
+        }
+    }
+
+    marbles
+}
+
+/// Compares `actual` against the `expected` marbles, returning a printable diff of the two
+/// diagrams if they differ.
+pub(crate) fn diff(expected: &str, actual: &[(u64, &str)], tick_ms: u64) -> Option<String> {
+    let expected_events = parse(expected, tick_ms).unwrap();
+    if expected_events == actual {
+        return None;
+    }
+
+    Some(format!(
+        "timelines differ (1 tick = {tick_ms}ms)\n\
+         expected: {}\n  \
+         actual: {}\n\
+         expected events: {expected_events:?}\n  \
+         actual events: {actual:?}",
+        render(&expected_events, tick_ms),
+        render(actual, tick_ms),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MarbleError, diff, parse, render};
+
+    #[test]
+    fn parses_values_groups_and_spaces() {
+        assert_eq!(
+            parse("a--b (cd)- e|", 100).unwrap(),
+            [
+                (0, "a"),
+                (300, "b"),
+                (400, "c"),
+                (400, "d"),
+                (900, "e"),
+                (1000, "|")
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_marbles() {
+        assert_eq!(
+            parse("a-#", 10),
+            Err(MarbleError::UnexpectedChar {
+                ch: '#',
+                position: 2
+            })
+        );
+        assert_eq!(
+            parse("((a))", 10),
+            Err(MarbleError::NestedGroup { position: 1 })
+        );
+        assert_eq!(
+            parse("a)", 10),
+            Err(MarbleError::UnopenedGroup { position: 1 })
+        );
+        assert_eq!(
+            parse("-(ab", 10),
+            Err(MarbleError::UnclosedGroup { position: 1 })
+        );
+    }
+
+    #[test]
+    fn render_round_trips_parsed_marbles() {
+        let marbles = "a-----b-----c-----d--(ef)-g";
+        assert_eq!(render(&parse(marbles, 25).unwrap(), 25), marbles);
+    }
+
+    #[test]
+    fn diff_renders_both_timelines() {
+        assert_eq!(diff("a--b", &[(0, "a"), (300, "b")], 100), None);
+
+        let diff = diff("a--b", &[(0, "a"), (200, "b")], 100).unwrap();
+        assert!(diff.contains("expected: a--b\n"), "{diff}");
+        assert!(diff.contains("actual: a-b\n"), "{diff}");
+    }
+}
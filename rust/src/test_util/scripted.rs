@@ -0,0 +1,278 @@
+//! A sender that plays a scripted timeline of values into a channel or stream, so a recipe's
+//! test only has to say what's sent when.
+
+use std::{
+    collections::VecDeque,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, ready},
+};
+
+use futures_core::Stream;
+use tokio::{
+    sync::{broadcast, mpsc, watch},
+    time::{Duration, Instant, Sleep, sleep_until},
+};
+
+use super::marble;
+use crate::{clock::Clock, envelope::EnvelopeSender, sequence::SequencedSender};
+
+/// A sender that [`ScriptedSender`] can play its values into.
+pub(crate) trait ScriptSink<T> {
+    /// Sends `value`, ignoring whether anything is still receiving, since the recipe under test
+    /// may have returned early on purpose.
+    fn send(&mut self, value: T) -> impl Future<Output = ()>;
+}
+
+impl<T> ScriptSink<T> for watch::Sender<T> {
+    async fn send(&mut self, value: T) {
+        let _ = watch::Sender::send(self, value);
+    }
+}
+
+impl<T> ScriptSink<T> for mpsc::Sender<T> {
+    async fn send(&mut self, value: T) {
+        let _ = mpsc::Sender::send(self, value).await;
+    }
+}
+
+impl<T> ScriptSink<T> for mpsc::UnboundedSender<T> {
+    async fn send(&mut self, value: T) {
+        let _ = mpsc::UnboundedSender::send(self, value);
+    }
+}
+
+impl<T> ScriptSink<T> for broadcast::Sender<T> {
+    async fn send(&mut self, value: T) {
+        let _ = broadcast::Sender::send(self, value);
+    }
+}
+
+impl<T> ScriptSink<T> for SequencedSender<T> {
+    async fn send(&mut self, value: T) {
+        let _ = SequencedSender::send(self, value);
+    }
+}
+
+impl<T, C: Clock> ScriptSink<T> for EnvelopeSender<T, C> {
+    async fn send(&mut self, value: T) {
+        let _ = EnvelopeSender::send(self, value);
+    }
+}
+
+/// Sends each value of a script at its offset, in milliseconds from when the script was created,
+/// then drops the sender.
+///
+/// Sends are scheduled against absolute deadlines, so a slow recipe doesn't push the rest of the
+/// script back, but a full `mpsc` channel does hold up the send that's waiting for room. The
+/// actual time of every send is recorded and returned once the script is done, which
+/// [`done`](ScriptedSender::done) also signals to other tasks.
+pub(crate) struct ScriptedSender<T> {
+    start: Instant,
+    steps: VecDeque<(u64, T)>,
+    close_at: Option<u64>,
+    done: watch::Sender<bool>,
+}
+
+impl<T> ScriptedSender<T> {
+    /// Creates a script that sends each `(offset, value)` pair, starting now.
+    pub(crate) fn new(steps: impl IntoIterator<Item = (u64, T)>) -> Self {
+        Self {
+            start: Instant::now(),
+            steps: steps.into_iter().collect(),
+            close_at: None,
+            done: watch::Sender::new(false),
+        }
+    }
+
+    /// Keeps the sender until `offset` rather than dropping it straight after the last value,
+    /// giving the recipe time to emit before it sees the channel close.
+    pub(crate) fn close_at(mut self, offset: u64) -> Self {
+        self.close_at = Some(offset);
+        self
+    }
+
+    /// Turns each value of the script into another with `f`.
+    pub(crate) fn map<U>(self, mut f: impl FnMut(T) -> U) -> ScriptedSender<U> {
+        ScriptedSender {
+            start: self.start,
+            steps: self.steps.into_iter().map(|(o, v)| (o, f(v))).collect(),
+            close_at: self.close_at,
+            done: self.done,
+        }
+    }
+
+    /// The offset that the script drops the sender at.
+    pub(crate) fn end(&self) -> u64 {
+        let last = self.steps.back().map_or(0, |&(offset, _)| offset);
+        self.close_at.map_or(last, |close_at| close_at.max(last))
+    }
+
+    /// A future that finishes once the script has sent its last value and dropped the sender.
+    pub(crate) fn done(&self) -> impl Future<Output = ()> + use<T> {
+        let mut done = self.done.subscribe();
+        async move {
+            // The script being dropped before it's played counts as done too.
+            let _ = done.wait_for(|done| *done).await;
+        }
+    }
+
+    /// Plays the script into `sink`, returning when each value was actually sent, in
+    /// milliseconds since the start.
+    pub(crate) async fn play(mut self, mut sink: impl ScriptSink<T>) -> Vec<u128> {
+        let mut sent = Vec::with_capacity(self.steps.len());
+        while let Some((offset, value)) = self.steps.pop_front() {
+            sleep_until(self.start + Duration::from_millis(offset)).await;
+            sink.send(value).await;
+            sent.push(self.start.elapsed().as_millis());
+        }
+        if let Some(close_at) = self.close_at {
+            sleep_until(self.start + Duration::from_millis(close_at)).await;
+        }
+        drop(sink);
+        self.done.send_replace(true);
+        sent
+    }
+
+    /// Turns the script into a stream that yields each value at its offset, and ends after the
+    /// last one, or at [`close_at`](ScriptedSender::close_at) if that's later.
+    pub(crate) fn into_stream(self) -> ScriptedStream<T> {
+        ScriptedStream {
+            sleep: Box::pin(sleep_until(self.start)),
+            script: self,
+            sent: Vec::new(),
+        }
+    }
+}
+
+impl<'a> ScriptedSender<&'a str> {
+    /// Creates a script from a marble diagram, where a `|` drops the sender.
+    pub(crate) fn marbles(marbles: &'a str, tick_ms: u64) -> Self {
+        let mut pairs = marble::parse(marbles, tick_ms).unwrap();
+        let close_at = pairs
+            .iter()
+            .position(|&(_, msg)| msg == "|")
+            .map(|index| pairs.drain(index..).next().unwrap().0);
+        Self {
+            close_at,
+            ..Self::new(pairs)
+        }
+    }
+}
+
+/// A [`ScriptedSender`] played as a [`Stream`], returned by
+/// [`ScriptedSender::into_stream`].
+pub(crate) struct ScriptedStream<T> {
+    script: ScriptedSender<T>,
+    sleep: Pin<Box<Sleep>>,
+    sent: Vec<u128>,
+}
+
+impl<T> ScriptedStream<T> {
+    /// When each value so far was yielded, in milliseconds since the start.
+    pub(crate) fn sent(&self) -> &[u128] {
+        &self.sent
+    }
+}
+
+impl<T: Unpin> Stream for ScriptedStream<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let this = &mut *self;
+        let script = &mut this.script;
+        let next = script
+            .steps
+            .front()
+            .map(|&(offset, _)| offset)
+            .or(script.close_at);
+        let Some(offset) = next else {
+            script.done.send_replace(true);
+            return Poll::Ready(None);
+        };
+
+        let deadline = script.start + Duration::from_millis(offset);
+        if this.sleep.deadline() != deadline {
+            this.sleep.as_mut().reset(deadline);
+        }
+        ready!(this.sleep.as_mut().poll(cx));
+
+        match script.steps.pop_front() {
+            Some((_, value)) => {
+                this.sent.push(script.start.elapsed().as_millis());
+                Poll::Ready(Some(value))
+            }
+            None => {
+                script.close_at = None;
+                script.done.send_replace(true);
+                Poll::Ready(None)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{future::poll_fn, pin::pin};
+
+    use futures_core::Stream;
+    use tokio::{
+        sync::{broadcast, mpsc},
+        time::{Duration, Instant, sleep_until},
+    };
+
+    use super::ScriptedSender;
+
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn plays_script_into_channels() {
+        let (tx, mut rx) = broadcast::channel(16);
+        let script = ScriptedSender::marbles("a--b-c--|", 100);
+        let start = Instant::now();
+        let done = script.done();
+
+        let (sent, received, ()) = tokio::join!(
+            script.play(tx),
+            async {
+                let mut received = Vec::new();
+                while let Ok(value) = rx.recv().await {
+                    received.push(value);
+                }
+                received
+            },
+            done,
+        );
+
+        assert_eq!(sent, [0, 300, 500]);
+        assert_eq!(received, ["a", "b", "c"]);
+        // The sender is held until the `|`.
+        assert_eq!(start.elapsed(), Duration::from_millis(800));
+
+        // A full channel holds up the send waiting for room, but not the ones after it.
+        let (tx, mut rx) = mpsc::channel(1);
+        let script = ScriptedSender::new([(0, 1), (0, 2), (100, 3), (400, 4)]);
+        let start = Instant::now();
+        let (sent, ()) = tokio::join!(script.play(tx), async {
+            sleep_until(start + Duration::from_millis(200)).await;
+            while rx.recv().await.is_some() {}
+        });
+        assert_eq!(sent, [0, 200, 200, 400]);
+    }
+
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn plays_script_as_stream() {
+        let script = ScriptedSender::new([(100, 'x'), (250, 'y')]).close_at(400);
+        let done = script.done();
+        let mut stream = pin!(script.into_stream());
+        let start = Instant::now();
+        let mut yielded = Vec::new();
+
+        while let Some(value) = poll_fn(|cx| stream.as_mut().poll_next(cx)).await {
+            yielded.push(value);
+        }
+        done.await;
+
+        assert_eq!(yielded, ['x', 'y']);
+        assert_eq!(stream.sent(), [100, 250]);
+        assert_eq!(start.elapsed(), Duration::from_millis(400));
+    }
+}
@@ -0,0 +1,748 @@
+use std::future::Future;
+
+use tokio::{
+    sync::{mpsc, watch},
+    time::Duration,
+};
+
+use crate::{
+    Action, Schedule, Summary, Throttle, ThrottleHandle, ThrottleStats,
+    clock::{Clock, TokioClock},
+    handle::{Command, next_command},
+    sequence::Sequence,
+};
+
+/// Which edges of a throttle window emit a value, with the same semantics as lodash/Rx `throttle`.
+///
+/// A window opens when a value arrives while the throttle is idle, and is restarted after every
+/// emit. Once a window closes without anything to emit, the throttle goes back to idle.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ThrottleEdge {
+    /// Emit the value that opens a window immediately and drop everything else sent during it.
+    Leading,
+    /// Emit the latest value at the end of each window, but never at its start.
+    Trailing,
+    /// Emit the value that opens a window immediately, and the latest value at the end of the
+    /// window if it changed in the meantime.
+    #[default]
+    Both,
+}
+
+impl ThrottleEdge {
+    pub(crate) fn leading(self) -> bool {
+        matches!(self, Self::Leading | Self::Both)
+    }
+
+    pub(crate) fn trailing(self) -> bool {
+        matches!(self, Self::Trailing | Self::Both)
+    }
+}
+
+/// Wraps a [`watch::Receiver`] so that its values are handed to a handler at most once per
+/// `interval`.
+///
+/// By default the first change is emitted immediately. After each emit the receiver sleeps for
+/// `interval`, and then emits whatever the latest value is, if it changed in the meantime. Any
+/// values that were overwritten during the sleep are never seen by the handler. See
+/// [`ThrottleEdge`] for the other modes, and [`Schedule`] for keeping emits on a fixed rate.
+///
+/// The decisions themselves are made by a [`Throttle`], which this drives from the channel and
+/// the timers of its [`Clock`].
+#[derive(Debug)]
+pub struct ThrottledReceiver<T, C = TokioClock> {
+    rx: watch::Receiver<T>,
+    throttle: Throttle<T>,
+    clock: C,
+}
+
+impl<T: Clone> ThrottledReceiver<T> {
+    /// Creates a throttled receiver that emits at most once per `interval`.
+    pub fn new(rx: watch::Receiver<T>, interval: Duration) -> Self {
+        Self {
+            rx,
+            throttle: Throttle::new(interval),
+            clock: TokioClock,
+        }
+    }
+}
+
+impl<T: Clone, C: Clock> ThrottledReceiver<T, C> {
+    /// Sets the clock that windows are timed by. Defaults to [`TokioClock`].
+    pub fn clock<C2: Clock>(self, clock: C2) -> ThrottledReceiver<T, C2> {
+        ThrottledReceiver {
+            rx: self.rx,
+            throttle: self.throttle,
+            clock,
+        }
+    }
+
+    /// Sets which edges of each window emit a value. Defaults to [`ThrottleEdge::Both`].
+    pub fn edge(mut self, edge: ThrottleEdge) -> Self {
+        self.throttle = self.throttle.edge(edge);
+        self
+    }
+
+    /// Sets how the end of each window is scheduled. Defaults to [`Schedule::FixedDelay`].
+    pub fn schedule(mut self, schedule: Schedule) -> Self {
+        self.throttle = self.throttle.schedule(schedule);
+        self
+    }
+
+    /// The minimum time between two emits.
+    pub fn interval(&self) -> Duration {
+        self.throttle.interval()
+    }
+
+    /// Runs the throttle loop, awaiting `handler` with each emitted value, until the
+    /// [`watch::Sender`] is dropped.
+    ///
+    /// If a trailing value is still waiting for its window to close when the sender is dropped,
+    /// it's flushed to the handler straight away rather than at the end of the window, since
+    /// nothing else can arrive to overwrite it.
+    pub async fn run<F, Fut>(mut self, handler: F) -> Summary
+    where
+        F: FnMut(T) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        self.run_loop(handler, None).await
+    }
+
+    async fn run_loop<F, Fut>(
+        &mut self,
+        mut handler: F,
+        mut commands: Option<mpsc::UnboundedReceiver<Command>>,
+    ) -> Summary
+    where
+        F: FnMut(T) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        let mut summary = Summary::default();
+        self.throttle
+            .set_wall_clock(self.clock.now(), self.clock.wall_time());
+
+        loop {
+            let deadline = self.throttle.next_deadline();
+            tokio::select! {
+                command = next_command(&mut commands) => {
+                    let value = match command {
+                        Command::Pause => {
+                            self.throttle.pause();
+                            None
+                        }
+                        Command::Resume => self.throttle.resume(self.clock.now()),
+                        Command::SetInterval(interval) => {
+                            self.throttle.set_interval(interval);
+                            None
+                        }
+                        Command::Flush => self.throttle.flush(self.clock.now()),
+                        Command::Stop => {
+                            if let Some(value) = self.throttle.on_close() {
+                                self.emit(&mut handler, value, &mut summary).await;
+                                summary.flushed_on_close = true;
+                            }
+                            return summary;
+                        }
+                    };
+                    if let Some(value) = value {
+                        self.emit(&mut handler, value, &mut summary).await;
+                    }
+                }
+                _ = self.clock.sleep_until(deadline.unwrap_or_else(|| self.clock.now())),
+                    if deadline.is_some() => {
+                    if let Some(value) = self.throttle.on_deadline(self.clock.now()) {
+                        self.emit(&mut handler, value, &mut summary).await;
+                    }
+                }
+                changed = self.rx.changed() => {
+                    if changed.is_err() {
+                        if let Some(value) = self.throttle.on_close() {
+                            self.emit(&mut handler, value, &mut summary).await;
+                            summary.flushed_on_close = true;
+                        }
+                        return summary;
+                    }
+                    let value = self.rx.borrow_and_update().clone();
+                    if let Action::Emit(value) = self.throttle.on_value(self.clock.now(), value) {
+                        self.emit(&mut handler, value, &mut summary).await;
+                    }
+                }
+            }
+        }
+    }
+
+    async fn emit<F, Fut>(&mut self, handler: &mut F, value: T, summary: &mut Summary)
+    where
+        F: FnMut(T) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        handler(value).await;
+        summary.emitted += 1;
+        self.throttle.on_handled(self.clock.now());
+    }
+}
+
+impl<T: Clone + Send + Sync + 'static, C: Clock> ThrottledReceiver<T, C> {
+    /// Runs the throttle loop on its own task with [`tokio::spawn`], so it can be driven by a
+    /// multi-threaded runtime. See [`ThrottledReceiver::run`] for how values are emitted, and
+    /// [`ThrottleHandle`] for controlling the receiver while it runs.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a tokio runtime.
+    pub fn spawn<F, Fut>(mut self, handler: F) -> ThrottleHandle
+    where
+        F: FnMut(T) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let (commands_tx, commands) = mpsc::unbounded_channel();
+        let task = tokio::spawn(async move { self.run_loop(handler, Some(commands)).await });
+        ThrottleHandle::new(task, commands_tx)
+    }
+}
+
+impl<T: Clone + Sequence, C: Clock> ThrottledReceiver<T, C> {
+    /// Like [`ThrottledReceiver::run`], but also tells the handler how many values were coalesced
+    /// into each emit, and returns the final [`ThrottleStats`] in the [`Summary`].
+    ///
+    /// Values that are never emitted, like those sent during a leading-only window or still
+    /// pending when the receiver stops, count as coalesced.
+    pub async fn run_tracked<F, Fut>(mut self, mut handler: F) -> Summary
+    where
+        F: FnMut(Emission<T>) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        // Only count values that haven't been seen yet. If the current value is unseen, any values
+        // it overwrote before this was called can't be told apart from ones sent before the
+        // receiver was created, so it's counted as the first received.
+        let first_seq = {
+            let current = self.rx.borrow();
+            current.sequence() - u64::from(current.has_changed())
+        };
+        let mut last_seq = first_seq;
+        let mut stats = ThrottleStats::default();
+
+        let mut summary = self
+            .run_loop(
+                |value: T| {
+                    let seq = value.sequence();
+                    let coalesced = seq.saturating_sub(last_seq + 1);
+                    last_seq = seq;
+                    stats.received = seq - first_seq;
+                    stats.emitted += 1;
+                    stats.coalesced += coalesced;
+                    handler(Emission {
+                        value,
+                        coalesced,
+                        stats,
+                    })
+                },
+                None,
+            )
+            .await;
+
+        stats.received = self.rx.borrow().sequence() - first_seq;
+        stats.coalesced = stats.received - stats.emitted;
+        summary.stats = Some(stats);
+        summary
+    }
+}
+
+/// A value emitted by [`ThrottledReceiver::run_tracked`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Emission<T> {
+    pub value: T,
+    /// How many values were sent since the previous emit and overwritten before this one.
+    pub coalesced: u64,
+    /// The stats so far, including this emit.
+    pub stats: ThrottleStats,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::SystemTime,
+    };
+
+    use tokio::{
+        sync::watch,
+        time::{Duration, Instant, MissedTickBehavior, sleep, sleep_until},
+    };
+
+    use super::{ThrottleEdge, ThrottledReceiver};
+    use crate::{
+        Schedule, Summary, ThrottleStats,
+        clock::{Clock, MockClock},
+        sequence,
+        test_util::{
+            Emitted, PAIRS, SPREAD_PAIRS, ScriptedSender, TICK_MS, assert_marbles, marble, replay,
+            replay_with_output,
+        },
+    };
+
+    /// Replays `marbles` through a [`ThrottledReceiver`] with a 1000ms interval.
+    async fn run_timeline(edge: ThrottleEdge, marbles: &str, tick_ms: u64) -> Vec<Emitted> {
+        replay(marbles, tick_ms, |rx, recorder| {
+            ThrottledReceiver::new(rx, Duration::from_millis(1000))
+                .edge(edge)
+                .run(move |value| {
+                    recorder.record(value);
+                    async {}
+                })
+        })
+        .await
+    }
+
+    /// Tests that [`ThrottledReceiver`] emits the expected messages for a scripted timeline.
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn throttle_outputs_expected_messages() {
+        let result = run_timeline(ThrottleEdge::Both, PAIRS, TICK_MS).await;
+
+        // "a" is read as soon as it's sent, and then "b", "d" and "i" on each second boundary.
+        let expected = concat!(
+            "a--------------------------------------- ",
+            "b--------------------------------------- ",
+            "d--------------------------------------- ",
+            "i",
+        );
+
+        assert_marbles(&result, expected, TICK_MS);
+    }
+
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn leading_edge_drops_values_inside_window() {
+        let result = run_timeline(ThrottleEdge::Leading, SPREAD_PAIRS, 100).await;
+
+        // "a" opens [0, 1000), so "b" is dropped and "c" opens [1200, 2200), dropping "d". "e"
+        // and "g" likewise each open a fresh window after the previous one went idle.
+        assert_marbles(&result, "a--------- --c------- -----e---- ------g", 100);
+    }
+
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn trailing_edge_emits_latest_at_window_end() {
+        let result = run_timeline(ThrottleEdge::Trailing, SPREAD_PAIRS, 100).await;
+
+        // "a" opens a window without being emitted and is overwritten by "b" before it closes.
+        // Every trailing emit restarts the window, so the boundaries stay on whole seconds.
+        assert_marbles(
+            &result,
+            "---------- b--------- d--------- f--------- g",
+            100,
+        );
+    }
+
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn both_edges_emit_leading_and_trailing_values() {
+        let result = run_timeline(ThrottleEdge::Both, SPREAD_PAIRS, 100).await;
+
+        assert_marbles(
+            &result,
+            "a--------- b--------- d--------- f--------- g",
+            100,
+        );
+    }
+
+    /// Replays `marbles` through a [`ThrottledReceiver`] with a 1000ms interval until it returns.
+    async fn run_to_completion(edge: ThrottleEdge, marbles: &str) -> (Vec<Emitted>, Summary) {
+        let (result, summary) = replay_with_output(marbles, 100, |rx, recorder| {
+            ThrottledReceiver::new(rx, Duration::from_millis(1000))
+                .edge(edge)
+                .run(move |value| {
+                    recorder.record(value);
+                    async {}
+                })
+        })
+        .await;
+        (result, summary.expect("throttled receiver never returned"))
+    }
+
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn dropping_sender_flushes_pending_value() {
+        let (result, summary) = run_to_completion(ThrottleEdge::Both, "a-b-|").await;
+
+        // "b" would have been emitted at 1000ms, but is flushed as soon as the sender is dropped.
+        assert_marbles(&result, "a---(b|)", 100);
+        assert_eq!(
+            summary,
+            Summary {
+                emitted: 2,
+                flushed_on_close: true,
+                stats: None,
+            }
+        );
+    }
+
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn dropping_sender_without_pending_value_returns_immediately() {
+        let (result, summary) = run_to_completion(ThrottleEdge::Both, "a|").await;
+
+        assert_marbles(&result, "a|", 100);
+        assert_eq!(
+            summary,
+            Summary {
+                emitted: 1,
+                flushed_on_close: false,
+                stats: None,
+            }
+        );
+    }
+
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn dropping_sender_only_flushes_trailing_edges() {
+        // A trailing-only window still holds "b", while leading-only drops it like any other
+        // value sent during its window.
+        let (result, summary) = run_to_completion(ThrottleEdge::Trailing, "ab|").await;
+        assert_marbles(&result, "--(b|)", 100);
+        assert_eq!(summary.emitted, 1);
+        assert!(summary.flushed_on_close);
+
+        let (result, summary) = run_to_completion(ThrottleEdge::Leading, "ab|").await;
+        assert_marbles(&result, "a-|", 100);
+        assert_eq!(summary.emitted, 1);
+        assert!(!summary.flushed_on_close);
+    }
+
+    /// Replays `marbles` through a [`ThrottledReceiver`] with a 1000ms interval and a handler
+    /// that takes `handler_ms` to run.
+    async fn run_slow_handler(
+        schedule: Schedule,
+        marbles: &str,
+        tick_ms: u64,
+        handler_ms: u64,
+    ) -> Vec<Emitted> {
+        replay(marbles, tick_ms, |rx, recorder| {
+            ThrottledReceiver::new(rx, Duration::from_millis(1000))
+                .schedule(schedule)
+                .run(move |value| {
+                    recorder.record(value);
+                    sleep(Duration::from_millis(handler_ms))
+                })
+        })
+        .await
+    }
+
+    fn fixed_rate(missed: MissedTickBehavior) -> Schedule {
+        Schedule::FixedRate {
+            missed,
+            align_to_wall_clock: false,
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn fixed_rate_does_not_drift_with_slow_handler() {
+        let delayed = run_slow_handler(Schedule::FixedDelay, PAIRS, TICK_MS, 300).await;
+        let fixed = run_slow_handler(fixed_rate(MissedTickBehavior::Burst), PAIRS, TICK_MS, 300);
+
+        // Each 300ms spent in the handler pushes the next window back, so "b" and "d" are both
+        // overwritten before their now-late windows close.
+        assert_marbles(
+            &delayed,
+            concat!(
+                "a--------------------------------------- ",
+                "------------c--------------------------- ",
+                "------------------------i",
+            ),
+            TICK_MS,
+        );
+        // The same output as with an instant handler.
+        assert_marbles(
+            &fixed.await,
+            concat!(
+                "a--------------------------------------- ",
+                "b--------------------------------------- ",
+                "d--------------------------------------- ",
+                "i",
+            ),
+            TICK_MS,
+        );
+    }
+
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn fixed_rate_restarts_grid_after_idle() {
+        let result = run_slow_handler(
+            fixed_rate(MissedTickBehavior::Burst),
+            "a--------- -----bc",
+            100,
+            0,
+        )
+        .await;
+
+        // The throttle goes idle at 1000ms, so "b" starts a new grid rather than opening a window
+        // that ends at 2000ms on the old one, and "c" waits a whole interval after it.
+        assert_marbles(&result, "a--------- -----b---- -----c", 100);
+    }
+
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn fixed_rate_missed_tick_behavior() {
+        // A value every 300ms, handled by a handler that takes 1300ms, so every tick is missed.
+        let marbles = "a--b--c--d --e--f--g- -h--i--j";
+        let run = |missed| run_slow_handler(fixed_rate(missed), marbles, 100, 1300);
+
+        // Burst emits as soon as the handler returns until the grid catches back up.
+        assert_marbles(
+            &run(MissedTickBehavior::Burst).await,
+            "a--------- ---e------ ------i--- ---------j",
+            100,
+        );
+        // Delay restarts the grid from when the handler returned.
+        assert_marbles(
+            &run(MissedTickBehavior::Delay).await,
+            "a--------- ---------- ---h------ ---------- ------j",
+            100,
+        );
+        // Skip waits for the next tick on the original grid.
+        assert_marbles(
+            &run(MissedTickBehavior::Skip).await,
+            "a--------- ---------- g--------- ---------- j",
+            100,
+        );
+    }
+
+    /// Sends `marbles` through a sequenced channel into [`ThrottledReceiver::run_tracked`],
+    /// dropping the sender after the last value, and returns each emitted value with how many
+    /// values were coalesced into it.
+    async fn run_tracked(
+        edge: ThrottleEdge,
+        marbles: &str,
+        tick_ms: u64,
+    ) -> (Vec<(&str, u64)>, Summary) {
+        let (tx, rx) = sequence::channel("");
+        let mut emitted = Vec::new();
+
+        let (_, summary) = tokio::join!(
+            ScriptedSender::marbles(marbles, tick_ms).play(tx),
+            ThrottledReceiver::new(rx, Duration::from_millis(1000))
+                .edge(edge)
+                .run_tracked(|emission| {
+                    emitted.push((emission.value.value, emission.coalesced));
+                    async {}
+                }),
+        );
+
+        (emitted, summary)
+    }
+
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn tracked_emissions_report_coalesced_values() {
+        let (emitted, summary) = run_tracked(ThrottleEdge::Both, PAIRS, TICK_MS).await;
+
+        // "c" is overwritten by "d", and "e" through "h" by "i", which is flushed once the sender
+        // is dropped straight after sending it.
+        assert_eq!(emitted, [("a", 0), ("b", 0), ("d", 1), ("i", 4)]);
+        assert_eq!(
+            summary.stats,
+            Some(ThrottleStats {
+                received: 9,
+                emitted: 4,
+                coalesced: 5,
+            })
+        );
+    }
+
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn tracked_stats_count_dropped_values_as_coalesced() {
+        let (emitted, summary) = run_tracked(ThrottleEdge::Leading, "a-b-c", 100).await;
+
+        assert_eq!(emitted, [("a", 0)]);
+        assert_eq!(
+            summary.stats,
+            Some(ThrottleStats {
+                received: 3,
+                emitted: 1,
+                coalesced: 2,
+            })
+        );
+    }
+
+    /// The spawned receiver runs on real time here, since the paused clock needs a single
+    /// threaded runtime. How late each task gets to run depends on the machine's load, so this
+    /// only checks what holds however the values are scheduled.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn spawned_receiver_runs_on_multi_thread_runtime() {
+        let (tx, rx) = watch::channel("");
+        let emitted = Arc::new(Mutex::new(Vec::new()));
+
+        let handle = ThrottledReceiver::new(rx, Duration::from_millis(400)).spawn({
+            let emitted = emitted.clone();
+            move |value| {
+                let emitted = emitted.clone();
+                async move {
+                    // Hand the rest of the handler to whichever worker picks it up next.
+                    tokio::task::yield_now().await;
+                    emitted.lock().unwrap().push((value, Instant::now()));
+                }
+            }
+        });
+
+        ScriptedSender::new([(0, "a"), (100, "b"), (200, "c"), (600, "d")])
+            .close_at(700)
+            .play(tx)
+            .await;
+
+        let summary = handle.await.unwrap();
+        let emitted = emitted.lock().unwrap();
+        let values: Vec<_> = emitted.iter().map(|&(value, _)| value).collect();
+        // Values are emitted in the order they were sent, and the last one is never lost.
+        assert!(values.is_sorted_by(|a, b| a < b), "{values:?}");
+        assert_eq!(values.last(), Some(&"d"));
+        // Only a value flushed on close can follow the previous emit by less than the interval.
+        for pair in emitted[..emitted.len() - 1].windows(2) {
+            assert!(
+                pair[1].1 - pair[0].1 >= Duration::from_millis(400),
+                "{values:?}"
+            );
+        }
+        assert_eq!(summary.emitted, values.len() as u64);
+        assert_eq!(summary.stats, None);
+    }
+
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn handle_controls_running_throttle() {
+        let (tx, rx) = watch::channel("");
+        let emitted = Arc::new(Mutex::new(Vec::new()));
+        let start = Instant::now();
+
+        let handle = ThrottledReceiver::new(rx, Duration::from_millis(1000)).spawn({
+            let emitted = emitted.clone();
+            move |value| {
+                emitted
+                    .lock()
+                    .unwrap()
+                    .push((value, start.elapsed().as_millis()));
+                async {}
+            }
+        });
+
+        let at = |time| sleep_until(start + Duration::from_millis(time));
+        tx.send("a").unwrap();
+        at(500).await;
+        handle.pause();
+        at(600).await;
+        tx.send("b").unwrap();
+        at(1200).await;
+        tx.send("c").unwrap();
+        at(1500).await;
+        handle.resume();
+        at(1600).await;
+        handle.set_interval(Duration::from_millis(500));
+        at(1700).await;
+        tx.send("d").unwrap();
+        at(2600).await;
+        tx.send("e").unwrap();
+        at(3100).await;
+        tx.send("f").unwrap();
+        at(3200).await;
+        handle.flush();
+        at(3250).await;
+        tx.send("g").unwrap();
+        at(3300).await;
+        handle.stop();
+
+        let summary = handle.await.unwrap();
+        // Nothing is emitted while paused, so "b" is replaced by "c" before the resume. The new
+        // interval only applies from the window after the one that was open when it was changed.
+        assert_eq!(
+            *emitted.lock().unwrap(),
+            [
+                ("a", 0),
+                ("c", 1500),
+                ("d", 2500),
+                ("e", 3000),
+                ("f", 3200),
+                ("g", 3300),
+            ]
+        );
+        assert_eq!(
+            summary,
+            Summary {
+                emitted: 6,
+                flushed_on_close: true,
+                stats: None,
+            }
+        );
+    }
+
+    /// Sends each value of `marbles` at its tick, moving `clock` forward one tick at a time and
+    /// letting the receiver catch up in between, and drops the sender at `end_ms`.
+    async fn play_on_mock_clock<'a>(
+        clock: &MockClock,
+        tx: watch::Sender<&'a str>,
+        marbles: &'a str,
+        tick_ms: u64,
+        end_ms: u64,
+    ) {
+        let pairs = marble::parse(marbles, tick_ms).unwrap();
+        let mut pairs = pairs.iter().peekable();
+        for tick in 0..=end_ms / tick_ms {
+            while let Some((_, msg)) = pairs.next_if(|(time, _)| *time == tick * tick_ms) {
+                tx.send(msg).unwrap();
+            }
+            // Let the receiver catch up with the tick before moving on to the next one.
+            for _ in 0..10 {
+                tokio::task::yield_now().await;
+            }
+            clock.advance(Duration::from_millis(tick_ms));
+        }
+    }
+
+    /// The same timeline as [`throttle_outputs_expected_messages`], timed by a [`MockClock`] that
+    /// the test moves forward one tick at a time instead of by tokio's paused clock.
+    #[tokio::test(flavor = "current_thread")]
+    async fn throttle_runs_on_mock_clock() {
+        let clock = MockClock::new();
+        let start = clock.now();
+        let (tx, rx) = watch::channel("");
+        let mut emitted = Vec::new();
+
+        let receiver = ThrottledReceiver::new(rx, Duration::from_millis(1000))
+            .clock(clock.clone())
+            .run(|value| {
+                emitted.push(((clock.now() - start).as_millis() as u64, value));
+                async {}
+            });
+        tokio::join!(
+            play_on_mock_clock(&clock, tx, PAIRS, TICK_MS, 4000),
+            receiver
+        );
+
+        let expected = concat!(
+            "a--------------------------------------- ",
+            "b--------------------------------------- ",
+            "d--------------------------------------- ",
+            "i",
+        );
+        if let Some(diff) = marble::diff(expected, &emitted, TICK_MS) {
+            panic!("{diff}");
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn aligned_ticks_follow_mock_wall_clock() {
+        let clock = MockClock::new();
+        let start = clock.now();
+        // The last whole second on the wall clock was 300ms ago.
+        clock.set_wall_time(SystemTime::UNIX_EPOCH + Duration::from_millis(12_300));
+        let (tx, rx) = watch::channel("");
+        let mut emitted = Vec::new();
+
+        let receiver = ThrottledReceiver::new(rx, Duration::from_millis(1000))
+            .schedule(Schedule::FixedRate {
+                missed: MissedTickBehavior::Burst,
+                align_to_wall_clock: true,
+            })
+            .clock(clock.clone())
+            .run(|value| {
+                emitted.push(((clock.now() - start).as_millis() as u64, value));
+                async {}
+            });
+        tokio::join!(
+            play_on_mock_clock(&clock, tx, "ab------c- ---------", 100, 2000),
+            receiver,
+        );
+
+        // The trailing edges land on whole wall-clock seconds, 700ms and 1700ms in.
+        if let Some(diff) = marble::diff("a------b-- -------c", &emitted, 100) {
+            panic!("{diff}");
+        }
+    }
+}
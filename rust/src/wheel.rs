@@ -0,0 +1,395 @@
+//! A hierarchical timer wheel, for scheduling many deadlines from a single task.
+//!
+//! Rather than giving every timer its own [`Sleep`](tokio::time::Sleep), the wheel buckets
+//! timers by how far away they are, and the task that owns it only sleeps until the earliest
+//! non-empty bucket. Inserting and removing a timer are O(1), and each timer is moved between
+//! buckets at most once per level as its deadline approaches.
+//!
+//! The layout follows tokio's own timer: six levels of 64 slots, where each slot of a level spans
+//! 64 times as many ticks as a slot of the level below it.
+
+use std::collections::VecDeque;
+
+use tokio::time::{Duration, Instant};
+
+const LEVELS: usize = 6;
+const SLOT_BITS: u32 = 6;
+const SLOTS: usize = 1 << SLOT_BITS;
+/// The furthest ahead a timer can be placed, in ticks. Timers due later than this wait in the top
+/// level and are placed again once it has turned far enough.
+const MAX_TICKS: u64 = (1 << (SLOT_BITS * LEVELS as u32)) - 1;
+
+/// Identifies a timer in a [`TimerWheel`], for removing it before it fires.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TimerKey {
+    index: usize,
+    generation: u64,
+}
+
+#[derive(Debug)]
+struct Entry<T> {
+    /// The tick the timer is due on.
+    when: u64,
+    value: T,
+    /// Where the timer is in the wheel, as `(level, slot, position in slot)`, or `None` once it
+    /// has expired and is waiting to be polled.
+    location: Option<(usize, usize, usize)>,
+}
+
+#[derive(Debug)]
+struct Slab<T> {
+    entries: Vec<(u64, Option<Entry<T>>)>,
+    free: Vec<usize>,
+}
+
+#[derive(Debug)]
+struct Level {
+    /// A bit per slot, set if the slot has any timers in it.
+    occupied: u64,
+    slots: [Vec<usize>; SLOTS],
+}
+
+/// A hierarchical timer wheel holding a value of type `T` per timer.
+///
+/// Deadlines are rounded up to the wheel's resolution, so timers never fire early, and are
+/// measured from the `start` the wheel was created with. Poll the wheel with the current time
+/// whenever [`next_deadline`](TimerWheel::next_deadline) has passed to take the expired timers.
+#[derive(Debug)]
+pub struct TimerWheel<T> {
+    start: Instant,
+    resolution: Duration,
+    /// Every tick before this one has been processed.
+    elapsed: u64,
+    levels: Vec<Level>,
+    slab: Slab<T>,
+    /// Timers that have expired but haven't been polled yet.
+    expired: VecDeque<TimerKey>,
+    len: usize,
+}
+
+impl<T> TimerWheel<T> {
+    /// Creates an empty wheel whose ticks are `resolution` long, counted from `start`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `resolution` is zero.
+    pub fn new(start: Instant, resolution: Duration) -> Self {
+        assert!(
+            !resolution.is_zero(),
+            "timer wheel resolution must be non-zero"
+        );
+        Self {
+            start,
+            resolution,
+            elapsed: 0,
+            levels: (0..LEVELS)
+                .map(|_| Level {
+                    occupied: 0,
+                    slots: std::array::from_fn(|_| Vec::new()),
+                })
+                .collect(),
+            slab: Slab {
+                entries: Vec::new(),
+                free: Vec::new(),
+            },
+            expired: VecDeque::new(),
+            len: 0,
+        }
+    }
+
+    /// How many timers are in the wheel, including expired ones that haven't been polled.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the wheel has no timers in it.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Adds a timer that expires at `deadline`, returning a key to remove it with.
+    pub fn insert(&mut self, deadline: Instant, value: T) -> TimerKey {
+        let since_start = deadline.saturating_duration_since(self.start);
+        let when = since_start.as_nanos().div_ceil(self.resolution.as_nanos());
+        let entry = Entry {
+            when: u64::try_from(when).unwrap_or(u64::MAX),
+            value,
+            location: None,
+        };
+
+        let key = match self.slab.free.pop() {
+            Some(index) => {
+                let (generation, slot) = &mut self.slab.entries[index];
+                *slot = Some(entry);
+                TimerKey {
+                    index,
+                    generation: *generation,
+                }
+            }
+            None => {
+                self.slab.entries.push((0, Some(entry)));
+                TimerKey {
+                    index: self.slab.entries.len() - 1,
+                    generation: 0,
+                }
+            }
+        };
+        self.len += 1;
+        self.place(key);
+        key
+    }
+
+    /// Removes a timer before it has been polled, returning its value, or `None` if it has
+    /// already been polled or removed.
+    pub fn remove(&mut self, key: TimerKey) -> Option<T> {
+        let entry = self.take(key)?;
+        if let Some((level, slot, position)) = entry.location {
+            self.unlink(level, slot, position);
+        }
+        // An expired timer is left in the queue, and skipped when it's polled since its key is
+        // now stale.
+        Some(entry.value)
+    }
+
+    /// When the wheel next needs to be polled, or `None` if it's empty.
+    ///
+    /// This can be earlier than any timer's deadline, when timers need moving to a lower level of
+    /// the wheel, in which case polling returns nothing.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        if !self.expired.is_empty() {
+            return Some(self.instant(self.elapsed));
+        }
+        self.next_expiration()
+            .map(|(_, _, deadline)| self.instant(deadline))
+    }
+
+    /// Takes a timer whose deadline is at or before `now`, if there is one.
+    pub fn poll(&mut self, now: Instant) -> Option<T> {
+        let now_tick = u64::try_from(
+            now.saturating_duration_since(self.start).as_nanos() / self.resolution.as_nanos(),
+        )
+        .unwrap_or(u64::MAX);
+
+        loop {
+            while let Some(key) = self.expired.pop_front() {
+                if let Some(entry) = self.take(key) {
+                    return Some(entry.value);
+                }
+            }
+
+            let (level, slot, deadline) = self.next_expiration()?;
+            if deadline > now_tick {
+                return None;
+            }
+            self.elapsed = deadline;
+            self.levels[level].occupied &= !(1 << slot);
+            for index in std::mem::take(&mut self.levels[level].slots[slot]) {
+                let generation = self.slab.entries[index].0;
+                self.place(TimerKey { index, generation });
+            }
+        }
+    }
+
+    /// Puts the timer behind `key` in the slot it belongs in, given the current tick, or in the
+    /// expired queue if it's due.
+    fn place(&mut self, key: TimerKey) {
+        let elapsed = self.elapsed;
+        let entry = self.slab.entries[key.index].1.as_mut().unwrap();
+        if entry.when <= elapsed {
+            entry.location = None;
+            self.expired.push_back(key);
+            return;
+        }
+
+        let placed = entry.when.min(elapsed + MAX_TICKS);
+        let level = level_for(elapsed, placed);
+        let slot = ((placed >> (level as u32 * SLOT_BITS)) as usize) % SLOTS;
+        let slots = &mut self.levels[level];
+        entry.location = Some((level, slot, slots.slots[slot].len()));
+        slots.slots[slot].push(key.index);
+        slots.occupied |= 1 << slot;
+    }
+
+    /// Removes the timer at `position` of a slot, moving the slot's last timer into its place.
+    fn unlink(&mut self, level: usize, slot: usize, position: usize) {
+        let slots = &mut self.levels[level];
+        slots.slots[slot].swap_remove(position);
+        if let Some(&moved) = slots.slots[slot].get(position) {
+            let entry = self.slab.entries[moved].1.as_mut().unwrap();
+            entry.location = Some((level, slot, position));
+        }
+        if slots.slots[slot].is_empty() {
+            slots.occupied &= !(1 << slot);
+        }
+    }
+
+    /// Takes the entry behind `key` out of the slab, if the key is still current.
+    fn take(&mut self, key: TimerKey) -> Option<Entry<T>> {
+        let (generation, slot) = self.slab.entries.get_mut(key.index)?;
+        if *generation != key.generation {
+            return None;
+        }
+        let entry = slot.take()?;
+        *generation += 1;
+        self.slab.free.push(key.index);
+        self.len -= 1;
+        Some(entry)
+    }
+
+    /// The earliest occupied slot across every level, as `(level, slot, tick it starts on)`.
+    fn next_expiration(&self) -> Option<(usize, usize, u64)> {
+        self.levels
+            .iter()
+            .enumerate()
+            .filter_map(|(level, slots)| {
+                if slots.occupied == 0 {
+                    return None;
+                }
+                let slot_ticks = 1u64 << (level as u32 * SLOT_BITS);
+                let level_ticks = slot_ticks << SLOT_BITS;
+                let now_slot = ((self.elapsed >> (level as u32 * SLOT_BITS)) as usize) % SLOTS;
+                let slot = (slots
+                    .occupied
+                    .rotate_right(now_slot as u32)
+                    .trailing_zeros() as usize
+                    + now_slot)
+                    % SLOTS;
+                let level_start = self.elapsed & !(level_ticks - 1);
+                let mut deadline = level_start + slot as u64 * slot_ticks;
+                // Only the top level can hold timers for its next rotation, behind the current
+                // slot.
+                if deadline <= self.elapsed {
+                    deadline += level_ticks;
+                }
+                Some((level, slot, deadline))
+            })
+            .min_by_key(|&(_, _, deadline)| deadline)
+    }
+
+    fn instant(&self, tick: u64) -> Instant {
+        let nanos = u64::try_from(self.resolution.as_nanos()).unwrap_or(u64::MAX);
+        self.start + Duration::from_nanos(tick.saturating_mul(nanos))
+    }
+}
+
+/// The level a timer due on tick `when` goes in, which is the level of the highest bit that
+/// differs between `when` and the current tick.
+fn level_for(elapsed: u64, when: u64) -> usize {
+    let masked = (elapsed ^ when) | (SLOTS as u64 - 1);
+    let significant = 63 - masked.leading_zeros();
+    ((significant / SLOT_BITS) as usize).min(LEVELS - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::time::Instant;
+
+    use super::{TimerWheel, level_for};
+    use crate::test_util::ms;
+
+    /// Polls `wheel` at each of its deadlines until it's empty, returning each value with the
+    /// millisecond it was polled at.
+    fn drain<T>(wheel: &mut TimerWheel<T>, start: Instant) -> Vec<(u128, T)> {
+        let mut fired = Vec::new();
+        while let Some(deadline) = wheel.next_deadline() {
+            while let Some(value) = wheel.poll(deadline) {
+                fired.push(((deadline - start).as_millis(), value));
+            }
+        }
+        fired
+    }
+
+    #[test]
+    fn levels_split_on_highest_differing_bit() {
+        assert_eq!(level_for(0, 1), 0);
+        assert_eq!(level_for(0, 63), 0);
+        assert_eq!(level_for(0, 64), 1);
+        assert_eq!(level_for(60, 70), 1);
+        assert_eq!(level_for(0, 4096), 2);
+        assert_eq!(level_for(0, (1 << 36) - 1), 5);
+        assert_eq!(level_for((1 << 36) - 1, 1 << 36), 5);
+    }
+
+    #[test]
+    fn timers_fire_in_order_across_levels() {
+        let start = Instant::now();
+        let mut wheel = TimerWheel::new(start, ms(1));
+        let deadlines = [300_000, 5, 64, 63, 4096, 65, 1, 4095, 9_000_000];
+        for deadline in deadlines {
+            wheel.insert(start + ms(deadline), deadline);
+        }
+        assert_eq!(wheel.len(), deadlines.len());
+
+        let mut expected = deadlines.map(|deadline| (u128::from(deadline), deadline));
+        expected.sort();
+        assert_eq!(drain(&mut wheel, start), expected);
+        assert!(wheel.is_empty());
+    }
+
+    #[test]
+    fn poll_only_returns_expired_timers() {
+        let start = Instant::now();
+        let mut wheel = TimerWheel::new(start, ms(1));
+        wheel.insert(start + ms(100), "a");
+        wheel.insert(start + ms(100), "b");
+        wheel.insert(start + ms(5000), "c");
+
+        assert_eq!(wheel.poll(start + ms(99)), None);
+        let mut due = [wheel.poll(start + ms(150)), wheel.poll(start + ms(150))];
+        due.sort();
+        assert_eq!(due, [Some("a"), Some("b")]);
+        assert_eq!(wheel.poll(start + ms(4999)), None);
+        assert_eq!(wheel.poll(start + ms(6000)), Some("c"));
+        assert_eq!(wheel.next_deadline(), None);
+    }
+
+    #[test]
+    fn removed_timers_never_fire() {
+        let start = Instant::now();
+        let mut wheel = TimerWheel::new(start, ms(1));
+        let a = wheel.insert(start + ms(10), "a");
+        let b = wheel.insert(start + ms(10), "b");
+        let c = wheel.insert(start + ms(2000), "c");
+        wheel.insert(start + ms(10), "d");
+
+        assert_eq!(wheel.remove(a), Some("a"));
+        assert_eq!(wheel.remove(a), None);
+        assert_eq!(wheel.remove(c), Some("c"));
+        assert_eq!(wheel.len(), 2);
+
+        // Removing an expired timer that hasn't been polled yet works too.
+        assert_eq!(wheel.poll(start + ms(10)), Some("d"));
+        assert_eq!(wheel.remove(b), Some("b"));
+        assert_eq!(wheel.poll(start + ms(10)), None);
+        assert!(wheel.is_empty());
+
+        // A freed index is reused without reviving the old key.
+        let e = wheel.insert(start + ms(20), "e");
+        assert_eq!(wheel.remove(a), None);
+        assert_eq!(drain(&mut wheel, start), [(20, "e")]);
+        assert_eq!(wheel.remove(e), None);
+    }
+
+    #[test]
+    fn deadlines_round_up_to_resolution() {
+        let start = Instant::now();
+        let mut wheel = TimerWheel::new(start, ms(10));
+        wheel.insert(start + ms(15), "a");
+        wheel.insert(start, "b");
+
+        assert_eq!(wheel.poll(start), Some("b"));
+        assert_eq!(wheel.next_deadline(), Some(start + ms(20)));
+        assert_eq!(wheel.poll(start + ms(19)), None);
+        assert_eq!(wheel.poll(start + ms(20)), Some("a"));
+    }
+
+    #[test]
+    fn deadlines_past_u32_ticks_keep_their_time() {
+        let start = Instant::now();
+        let mut wheel = TimerWheel::new(start, ms(1));
+        // Over 2^32 ticks away, about 58 days at this resolution.
+        wheel.insert(start + ms(5_000_000_000), "a");
+
+        assert_eq!(drain(&mut wheel, start), [(5_000_000_000, "a")]);
+    }
+}